@@ -0,0 +1,127 @@
+#[macro_use]
+extern crate criterion;
+extern crate crossbeam;
+extern crate mandelbuddha;
+extern crate num;
+extern crate rand;
+
+use std::env;
+use std::thread;
+
+use criterion::Criterion;
+use mandelbuddha::aggregators::{Aggregator, FileAggregator, SharedAggregator};
+use mandelbuddha::config::{Counter, Orbits};
+use mandelbuddha::header::{BandStatistics, Header};
+use num::complex::Complex64;
+
+const THREADS: usize = 4;
+const POINTS: usize = 250_000;
+const THREAD_BUFFER: usize = 10_000;
+
+fn header() -> Header {
+    Header {
+        width: 1000,
+        height: 1000,
+        min: Complex64::new(-2.0, -2.0),
+        max: Complex64::new(2.0, 2.0),
+        min_iterations: 0,
+        max_iterations: 1,
+        samples: 0,
+        formula: "bench".to_owned(),
+        orbits: Orbits::Escaping,
+        counter: Counter::U32,
+        band: BandStatistics::default(),
+    }
+}
+
+fn points() -> Vec<Vec<Complex64>> {
+    (0..THREADS)
+        .map(|_| {
+            (0..POINTS)
+                .map(|_| {
+                    Complex64::new(
+                        rand::random::<f64>() * 4.0 - 2.0,
+                        rand::random::<f64>() * 4.0 - 2.0,
+                    )
+                }).collect()
+        }).collect()
+}
+
+fn file_name(name: &str) -> String {
+    env::temp_dir()
+        .join(format!("mandelbuddha-bench-{}.mbh", name))
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn criterion_benchmark(c: &mut Criterion) {
+    c.bench_function("channel pipeline", |b| {
+        b.iter_with_setup(
+            || {
+                let aggregator =
+                    FileAggregator::create(&file_name("channel"), header(), 100_000, 30_000)
+                        .unwrap();
+                (aggregator, points())
+            },
+            |(mut aggregator, points)| {
+                let (sender, receiver) = crossbeam::channel::bounded::<Vec<Complex64>>(4);
+
+                let workers = points
+                    .into_iter()
+                    .map(|points| {
+                        let sender = sender.clone();
+                        thread::spawn(move || {
+                            for chunk in points.chunks(THREAD_BUFFER) {
+                                sender.send(chunk.to_vec());
+                            }
+                        })
+                    }).collect::<Vec<_>>();
+                drop(sender);
+
+                for chunk in receiver {
+                    for c in chunk {
+                        aggregator.aggregate(c);
+                    }
+                }
+                for worker in workers {
+                    worker.join().unwrap();
+                }
+                aggregator.flush().unwrap();
+            },
+        )
+    });
+
+    c.bench_function("shared atomic histogram", |b| {
+        b.iter_with_setup(
+            || {
+                let aggregator =
+                    SharedAggregator::create(&file_name("shared"), header(), 100_000).unwrap();
+                (aggregator, points())
+            },
+            |(mut aggregator, points)| {
+                let workers = points
+                    .into_iter()
+                    .map(|points| {
+                        let mut aggregator = aggregator.clone();
+                        thread::spawn(move || {
+                            for c in points {
+                                aggregator.aggregate(c);
+                            }
+                        })
+                    }).collect::<Vec<_>>();
+
+                for worker in workers {
+                    worker.join().unwrap();
+                }
+                aggregator.flush().unwrap();
+            },
+        )
+    });
+}
+
+criterion_group!{
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = criterion_benchmark
+}
+criterion_main!(benches);
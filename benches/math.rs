@@ -1,9 +1,14 @@
 #[macro_use]
 extern crate criterion;
+extern crate mandelbuddha;
 extern crate num;
 extern crate rand;
 
 use criterion::Criterion;
+use mandelbuddha::formulas::{Formula, Quadratic};
+use mandelbuddha::math;
+use mandelbuddha::simd;
+use mandelbuddha::simd::Kernel;
 use num::complex::Complex64;
 
 const ITERATIONS: usize = 10000;
@@ -68,5 +73,196 @@ fn criterion_benchmark(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, criterion_benchmark);
+/// Interior points settling into cycles of different periods, and an exterior point that never
+/// does. Without cycle detection, every interior point takes all `ITERATIONS`.
+const POINTS: [(&str, f64, f64); 4] = [
+    ("cardioid", -0.1, 0.1),
+    ("period 2 bulb", -1.0, 0.1),
+    ("period 3 bulb", -0.12, 0.75),
+    ("exterior", -0.75, 0.11),
+];
+
+fn cycle_benchmark(c: &mut Criterion) {
+    let bailout_min = Complex64::new(-2.0, -2.0);
+    let bailout_max = Complex64::new(2.0, 2.0);
+
+    for &(name, re, im) in &POINTS {
+        let point = Complex64::new(re, im);
+
+        c.bench_function(&format!("bailout without cycle detection, {}", name), move |b| {
+            b.iter(|| {
+                let mut z = Complex64::new(0.0, 0.0);
+                let mut iterations = 0;
+
+                while -2.0 < z.re && z.re < 2.0 && -2.0 < z.im && z.im < 2.0 && iterations < ITERATIONS {
+                    z = z * z + point;
+                    iterations += 1;
+                }
+
+                (z, iterations)
+            })
+        });
+        c.bench_function(&format!("bailout with cycle detection, {}", name), move |b| {
+            b.iter(|| {
+                math::calculate_bailout_iteration(
+                    &mut Quadratic::default().with_c(point),
+                    Complex64::new(0.0, 0.0),
+                    bailout_min,
+                    bailout_max,
+                    ITERATIONS,
+                )
+            })
+        });
+    }
+
+    let point = Complex64::new(-1.0, 0.1);
+    c.bench_function("iteration values with cycle detection, period 2 bulb", move |b| {
+        b.iter_with_setup(
+            || Vec::with_capacity(ITERATIONS),
+            |mut buffer| {
+                math::calculate_iteration_values(
+                    &mut Quadratic::default().with_c(point),
+                    Complex64::new(0.0, 0.0),
+                    bailout_min,
+                    bailout_max,
+                    0,
+                    ITERATIONS,
+                    &mut buffer,
+                );
+                buffer
+            },
+        )
+    });
+}
+
+fn bulb_benchmark(c: &mut Criterion) {
+    let bailout_min = Complex64::new(-2.0, -2.0);
+    let bailout_max = Complex64::new(2.0, 2.0);
+    let samples = (0..1000)
+        .map(|_| {
+            Complex64::new(
+                rand::random::<f64>() * 4.0 - 2.0,
+                rand::random::<f64>() * 4.0 - 2.0,
+            )
+        }).collect::<Vec<_>>();
+
+    // Iterations a loop without cycle detection spends on every sample. Rejected points never
+    // escape, so they would take all of them.
+    let plain = |c: Complex64| {
+        let mut z = Complex64::new(0.0, 0.0);
+        let mut iterations = 0;
+        while -2.0 < z.re && z.re < 2.0 && -2.0 < z.im && z.im < 2.0 && iterations < ITERATIONS {
+            z = z * z + c;
+            iterations += 1;
+        }
+        iterations
+    };
+    let total = samples.iter().map(|&c| plain(c)).sum::<usize>();
+    let rejected = samples
+        .iter()
+        .filter(|&&c| math::is_inside_mandelbrot_bulb(c))
+        .collect::<Vec<_>>();
+    println!(
+        "The bulb test rejects {} of {} samples, saving {} of {} check_iterations",
+        rejected.len(),
+        samples.len(),
+        rejected.iter().map(|&&c| plain(c)).sum::<usize>(),
+        total
+    );
+
+    let without = samples.clone();
+    c.bench_function("1000 samples without bulb test", move |b| {
+        b.iter(|| {
+            without
+                .iter()
+                .filter_map(|&c| {
+                    math::calculate_bailout_iteration(
+                        &mut Quadratic::default().with_c(c),
+                        Complex64::new(0.0, 0.0),
+                        bailout_min,
+                        bailout_max,
+                        ITERATIONS,
+                    )
+                }).sum::<usize>()
+        })
+    });
+    c.bench_function("1000 samples with bulb test", move |b| {
+        b.iter(|| {
+            samples
+                .iter()
+                .filter(|&&c| !math::is_inside_mandelbrot_bulb(c))
+                .filter_map(|&c| {
+                    math::calculate_bailout_iteration(
+                        &mut Quadratic::default().with_c(c),
+                        Complex64::new(0.0, 0.0),
+                        bailout_min,
+                        bailout_max,
+                        ITERATIONS,
+                    )
+                }).sum::<usize>()
+        })
+    });
+}
+
+fn kernel_benchmark(c: &mut Criterion) {
+    let bailout_min = Complex64::new(-2.0, -2.0);
+    let bailout_max = Complex64::new(2.0, 2.0);
+    // Samples as `generate` iterates them, with the bulbs rejected beforehand.
+    let samples = (0..1000)
+        .map(|_| {
+            Complex64::new(
+                rand::random::<f64>() * 4.0 - 2.0,
+                rand::random::<f64>() * 4.0 - 2.0,
+            )
+        }).filter(|&c| !math::is_inside_mandelbrot_bulb(c))
+        .collect::<Vec<_>>();
+
+    let scalar = samples.clone();
+    c.bench_function("bailout of 1000 samples, scalar", move |b| {
+        b.iter(|| {
+            scalar
+                .iter()
+                .map(|&c| {
+                    math::calculate_bailout_iteration(
+                        &mut Quadratic::default().with_c(c),
+                        Complex64::new(0.0, 0.0),
+                        bailout_min,
+                        bailout_max,
+                        ITERATIONS,
+                    )
+                }).collect::<Vec<_>>()
+        })
+    });
+
+    for &kernel in &[Kernel::Lanes, Kernel::Avx2] {
+        if !kernel.is_available() {
+            continue;
+        }
+
+        let samples = samples.clone();
+        c.bench_function(&format!("bailout of 1000 samples, {:?}", kernel), move |b| {
+            b.iter(|| {
+                let mut results = vec![None; samples.len()];
+                simd::quadratic_bailout_iterations(
+                    kernel,
+                    &samples,
+                    Complex64::new(0.0, 0.0),
+                    bailout_min,
+                    bailout_max,
+                    ITERATIONS,
+                    &mut results,
+                );
+                results
+            })
+        });
+    }
+}
+
+criterion_group!(
+    benches,
+    criterion_benchmark,
+    cycle_benchmark,
+    bulb_benchmark,
+    kernel_benchmark
+);
 criterion_main!(benches);
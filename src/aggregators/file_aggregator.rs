@@ -2,15 +2,18 @@ use num::complex::Complex64;
 use std::fs::{File, OpenOptions};
 use std::io;
 use std::io::Write;
-use std::mem;
 
 use file;
+use header;
+use header::Header;
 use math;
 use vec;
+use aggregators::journal::Journal;
 use aggregators::Aggregator;
 
 pub struct FileAggregator {
     file: File,
+    header: Header,
 
     file_width: u64,
     file_height: u64,
@@ -20,35 +23,20 @@ pub struct FileAggregator {
     file_buffer_size: usize,
     pixel_buffer_cutoff_size: usize,
 
-    file_buffer: Vec<u32>,
+    file_buffer: Vec<u64>,
     pixel_buffers: Vec<Vec<(u64, u64)>>,
+
+    journal: Option<Journal>,
 }
 impl FileAggregator {
     pub fn new(
         file: File,
-        file_width: u64,
-        file_height: u64,
-        file_min: Complex64,
-        file_max: Complex64,
+        header: Header,
         file_buffer_size: usize,
         pixel_buffer_cutoff_size: usize,
     ) -> io::Result<FileAggregator> {
-        let mut aggregator = FileAggregator {
-            file,
-            file_width,
-            file_height,
-            file_min,
-            file_max,
-            file_buffer_size,
-            pixel_buffer_cutoff_size,
-
-            file_buffer: vec::filled_with(0, file_buffer_size),
-
-            pixel_buffers: vec::filled_with(
-                Vec::new(),
-                (file_width * file_height / file_buffer_size as u64) as usize + 1,
-            ),
-        };
+        let mut aggregator =
+            FileAggregator::with_file(file, header, file_buffer_size, pixel_buffer_cutoff_size);
 
         aggregator.setup_file()?;
 
@@ -57,10 +45,7 @@ impl FileAggregator {
 
     pub fn create(
         file_name: &str,
-        file_width: u64,
-        file_height: u64,
-        file_min: Complex64,
-        file_max: Complex64,
+        header: Header,
         file_buffer_size: usize,
         pixel_buffer_cutoff_size: usize,
     ) -> io::Result<FileAggregator> {
@@ -69,53 +54,142 @@ impl FileAggregator {
             .write(true)
             .truncate(true)
             .create(true)
-            .open(file_name)
-            .unwrap();
+            .open(file_name)?;
 
         FileAggregator::new(
+            file,
+            header,
+            file_buffer_size,
+            pixel_buffer_cutoff_size,
+        )
+    }
+
+    /// Reopens a file written by a previous run and keeps adding to it.
+    pub fn open(
+        file_name: &str,
+        header: Header,
+        file_buffer_size: usize,
+        pixel_buffer_cutoff_size: usize,
+    ) -> io::Result<FileAggregator> {
+        let mut file = OpenOptions::new().read(true).write(true).open(file_name)?;
+
+        match Header::read(&mut file)? {
+            Some(ref existing) if existing.is_compatible(&header) => {}
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} does not match the configured image", file_name),
+                ))
+            }
+        }
+
+        Ok(FileAggregator::with_file(
+            file,
+            header,
+            file_buffer_size,
+            pixel_buffer_cutoff_size,
+        ))
+    }
+
+    fn with_file(
+        file: File,
+        header: Header,
+        file_buffer_size: usize,
+        pixel_buffer_cutoff_size: usize,
+    ) -> FileAggregator {
+        let (file_width, file_height) = (header.width, header.height);
+
+        FileAggregator {
             file,
             file_width,
             file_height,
-            file_min,
-            file_max,
+            file_min: header.min,
+            file_max: header.max,
+            header,
             file_buffer_size,
             pixel_buffer_cutoff_size,
+
+            file_buffer: vec::filled_with(0, file_buffer_size),
+
+            pixel_buffers: vec::filled_with(
+                Vec::new(),
+                (file_width * file_height / file_buffer_size as u64) as usize + 1,
+            ),
+
+            journal: None,
+        }
+    }
+
+    /// Undoes all writes made after the checkpoint at `samples_done`, using the journal left
+    /// behind by an interrupted run.
+    pub fn rollback(&mut self, journal: &str, samples_done: usize) -> io::Result<bool> {
+        Journal::rollback(
+            journal,
+            &mut self.file,
+            header::SIZE,
+            self.header.counter,
+            self.file_buffer_size,
+            samples_done as u64,
         )
     }
 
+    /// Journals all further writes, so the file can be rolled back to the checkpoint at
+    /// `samples_done`.
+    pub fn enable_journal(&mut self, journal: &str, samples_done: usize) -> io::Result<()> {
+        self.journal = Some(Journal::create(
+            journal,
+            self.pixel_buffers.len(),
+            samples_done as u64,
+        )?);
+        Ok(())
+    }
+
     fn setup_file(&mut self) -> io::Result<()> {
-        let buffer = vec![0u8]
-            .iter()
-            .map(|i| *i)
-            .cycle()
-            .take(self.file_buffer_size * mem::size_of::<u32>())
-            .collect::<Vec<u8>>();
+        self.header.write(&mut self.file)?;
+
+        let buffer = vec![0u8; self.file_buffer_size * self.header.counter.bytes()];
 
         for _ in 0..(self.file_width * self.file_height) / self.file_buffer_size as u64 + 1 {
-            self.file.write(&buffer)?;
+            self.file.write_all(&buffer)?;
         }
 
         Ok(())
     }
 
     fn write_pixel_buffer(&mut self, buffer: usize) -> io::Result<()> {
-        file::read_u32(
+        // The last buffer ends with the image; files of other runs may not be padded past it.
+        let start = buffer as u64 * self.file_buffer_size as u64;
+        let pixels = self.file_width * self.file_height;
+        let length = (pixels - start).min(self.file_buffer_size as u64) as usize;
+
+        file::read_counts(
             &mut self.file,
-            buffer as u64 * self.file_buffer_size as u64,
-            &mut self.file_buffer,
+            header::SIZE,
+            self.header.counter,
+            start,
+            &mut self.file_buffer[..length],
         )?;
 
+        if let Some(ref mut journal) = self.journal {
+            journal.save(buffer, &self.file_buffer[..length])?;
+        }
+
+        let ceiling = self.header.counter.ceiling();
         for (x, y) in self.pixel_buffers[buffer].iter() {
             let location = y * self.file_width + x;
             let location = (location as usize) % self.file_buffer_size;
 
-            self.file_buffer[location as usize] += 1;
+            if self.file_buffer[location] < ceiling {
+                self.file_buffer[location] += 1;
+            }
         }
 
-        file::write_u32(
+        file::write_counts(
             &mut self.file,
-            buffer as u64 * self.file_buffer_size as u64,
-            &mut self.file_buffer,
+            header::SIZE,
+            self.header.counter,
+            start,
+            &self.file_buffer[..length],
         )?;
         Ok(())
     }
@@ -144,14 +218,27 @@ impl Aggregator for FileAggregator {
             }
         }
     }
-}
-impl Drop for FileAggregator {
-    fn drop(&mut self) {
+
+    fn flush(&mut self) -> io::Result<()> {
         for i in 0..self.pixel_buffers.len() {
-            if self.pixel_buffers[i].len() > 0 {
-                self.write_pixel_buffer(i)
-                    .expect("Error while writing pixel buffer");
+            if !self.pixel_buffers[i].is_empty() {
+                self.write_pixel_buffer(i)?;
+                self.pixel_buffers[i].clear();
             }
         }
+
+        self.file.sync_data()
+    }
+
+    fn commit(&mut self, samples_done: usize) -> io::Result<()> {
+        match self.journal {
+            Some(ref mut journal) => journal.reset(samples_done as u64),
+            None => Ok(()),
+        }
+    }
+}
+impl Drop for FileAggregator {
+    fn drop(&mut self) {
+        self.flush().expect("Error while writing pixel buffer");
     }
 }
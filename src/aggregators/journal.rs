@@ -0,0 +1,134 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::mem;
+
+use config::Counter;
+use file;
+use vec;
+
+/// Rollback journal for the file buffers of a `FileAggregator`.
+///
+/// Before a file buffer is overwritten for the first time after a checkpoint, its previous
+/// contents are appended here. Replaying the journal therefore restores the file to the state of
+/// the checkpoint it was started at, even if the process died while writing.
+pub struct Journal {
+    file: File,
+    length: u64,
+    saved: Vec<bool>,
+}
+impl Journal {
+    /// Starts an empty journal for a file matching the checkpoint after `samples_done` samples.
+    pub fn create(path: &str, buffers: usize, samples_done: u64) -> io::Result<Journal> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+
+        let mut journal = Journal {
+            file,
+            length: 0,
+            saved: vec::filled_with(false, buffers),
+        };
+        journal.reset(samples_done)?;
+
+        Ok(journal)
+    }
+
+    pub fn reset(&mut self, samples_done: u64) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&samples_done.to_le_bytes())?;
+        self.file.sync_data()?;
+
+        self.length = mem::size_of::<u64>() as u64;
+        for saved in &mut self.saved {
+            *saved = false;
+        }
+
+        Ok(())
+    }
+
+    /// Saves the contents of a buffer unless it was already saved since the last reset.
+    pub fn save(&mut self, buffer: usize, contents: &[u64]) -> io::Result<()> {
+        if self.saved[buffer] {
+            return Ok(());
+        }
+
+        self.file.seek(SeekFrom::Start(self.length))?;
+        self.file.write_all(&(buffer as u64).to_le_bytes())?;
+        self.file.write_all(&(contents.len() as u64).to_le_bytes())?;
+        file::write_u64(&mut self.file, self.length + 16, 0, contents)?;
+        self.file.sync_data()?;
+
+        self.length += 16 + mem::size_of_val(contents) as u64;
+        self.saved[buffer] = true;
+
+        Ok(())
+    }
+
+    /// Restores all saved buffers into `target`, which stores counts of `counter` width, if the
+    /// journal was started at `samples_done`. Returns whether anything was restored.
+    pub fn rollback(
+        path: &str,
+        target: &mut File,
+        offset: u64,
+        counter: Counter,
+        buffer_size: usize,
+        samples_done: u64,
+    ) -> io::Result<bool> {
+        let mut journal = match File::open(path) {
+            Ok(journal) => journal,
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(error) => return Err(error),
+        };
+
+        match read_u64(&mut journal)? {
+            Some(started_at) if started_at == samples_done => {}
+            _ => return Ok(false),
+        }
+
+        let mut position = mem::size_of::<u64>() as u64;
+        let mut contents = vec::filled_with(0u64, buffer_size);
+        let mut restored = false;
+        while let (Some(buffer), Some(length)) = (read_u64(&mut journal)?, read_u64(&mut journal)?) {
+            let length = length as usize;
+            if length > contents.len() {
+                contents.resize(length, 0);
+            }
+
+            // A record cut short was never applied to the target.
+            match file::read_u64(&mut journal, position + 16, 0, &mut contents[..length]) {
+                Ok(()) => {}
+                Err(ref error) if error.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(error) => return Err(error),
+            }
+
+            file::write_counts(
+                target,
+                offset,
+                counter,
+                buffer * buffer_size as u64,
+                &contents[..length],
+            )?;
+            restored = true;
+
+            position += 16 + (length * mem::size_of::<u64>()) as u64;
+            journal.seek(SeekFrom::Start(position))?;
+        }
+        target.sync_data()?;
+
+        Ok(restored)
+    }
+}
+
+fn read_u64(file: &mut File) -> io::Result<Option<u64>> {
+    let mut bytes = [0u8; 8];
+    match file.read_exact(&mut bytes) {
+        Ok(()) => Ok(Some(u64::from_le_bytes(bytes))),
+        Err(ref error) if error.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(error) => Err(error),
+    }
+}
@@ -1,9 +1,13 @@
 use std::fs::{File, OpenOptions};
+use std::io;
 
 use num::complex::Complex64;
 
 use aggregators::Aggregator;
+use config::Counter;
 use file;
+use header;
+use header::Header;
 use math;
 use vec;
 
@@ -18,32 +22,30 @@ pub struct MemoryAggregator {
 
     file_buffer_size: usize,
 
-    data: Vec<u32>,
+    counter: Counter,
+    data: Vec<u64>,
 }
 impl MemoryAggregator {
-    pub fn new(
-        file: &str,
-        width: u64,
-        height: u64,
-        min: Complex64,
-        max: Complex64,
-        file_buffer_size: usize,
-    ) -> MemoryAggregator {
+    pub fn new(file: &str, header: Header, file_buffer_size: usize) -> MemoryAggregator {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(file)
+            .unwrap();
+        header.write(&mut file).unwrap();
+
         MemoryAggregator {
-            file: OpenOptions::new()
-                .write(true)
-                .create(true)
-                .truncate(true)
-                .open(file)
-                .unwrap(),
-            width,
-            height,
-            min,
-            max,
+            file,
+            width: header.width,
+            height: header.height,
+            min: header.min,
+            max: header.max,
 
             file_buffer_size,
 
-            data: vec::filled_with(0u32, width as usize * height as usize),
+            counter: header.counter,
+            data: vec::filled_with(0u64, header.pixels() as usize),
         }
     }
 }
@@ -51,16 +53,30 @@ impl Aggregator for MemoryAggregator {
     fn aggregate(&mut self, c: Complex64) {
         let (x, y) = math::complex_to_image(c, self.min, self.max, self.width, self.height);
 
-        if x < self.width && y < self.width {
+        if x < self.width && y < self.height {
             let l = y * self.width + x;
-            self.data[l as usize] += 1;
+            if self.data[l as usize] < self.counter.ceiling() {
+                self.data[l as usize] += 1;
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        for (i, chunk) in self.data[..].chunks(self.file_buffer_size).enumerate() {
+            file::write_counts(
+                &mut self.file,
+                header::SIZE,
+                self.counter,
+                (i * self.file_buffer_size) as u64,
+                chunk,
+            )?;
         }
+
+        self.file.sync_data()
     }
 }
 impl Drop for MemoryAggregator {
     fn drop(&mut self) {
-        for (i, chunk) in self.data[..].chunks(self.file_buffer_size).enumerate() {
-            file::write_u32(&mut self.file, (i * self.file_buffer_size) as u64, chunk).unwrap();
-        }
+        self.flush().unwrap();
     }
 }
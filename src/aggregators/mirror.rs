@@ -0,0 +1,37 @@
+use std::io;
+
+use num::complex::Complex64;
+
+use aggregators::Aggregator;
+
+/// Deposits every point a second time mirrored at the real axis when enabled, which completes
+/// the images of conjugate symmetric formulas when only the upper half plane is sampled.
+#[derive(Clone)]
+pub struct Mirror<A> {
+    aggregator: A,
+    enabled: bool,
+}
+impl<A: Aggregator> Mirror<A> {
+    pub fn new(aggregator: A, enabled: bool) -> Mirror<A> {
+        Mirror {
+            aggregator,
+            enabled,
+        }
+    }
+}
+impl<A: Aggregator> Aggregator for Mirror<A> {
+    fn aggregate(&mut self, c: Complex64) {
+        self.aggregator.aggregate(c);
+        if self.enabled {
+            self.aggregator.aggregate(c.conj());
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.aggregator.flush()
+    }
+
+    fn commit(&mut self, samples_done: usize) -> io::Result<()> {
+        self.aggregator.commit(samples_done)
+    }
+}
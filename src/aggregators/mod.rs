@@ -1,12 +1,24 @@
 
-use num::complex::Complex64;
+use std::io;
 
+use num::complex::Complex64;
 
 mod file_aggregator;
+mod journal;
 pub use self::file_aggregator::FileAggregator;
 mod memory_aggregator;
 pub use self::memory_aggregator::MemoryAggregator;
+mod mirror;
+pub use self::mirror::Mirror;
+mod shared_aggregator;
+pub use self::shared_aggregator::SharedAggregator;
 
 pub trait Aggregator {
     fn aggregate(&mut self, c: Complex64);
-}
\ No newline at end of file
+    /// Writes everything aggregated so far to disk.
+    fn flush(&mut self) -> io::Result<()>;
+    /// Called once the checkpoint after `samples_done` samples covering the last flush is saved.
+    fn commit(&mut self, _samples_done: usize) -> io::Result<()> {
+        Ok(())
+    }
+}
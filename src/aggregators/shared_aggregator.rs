@@ -0,0 +1,295 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use num::complex::Complex64;
+
+use aggregators::journal::Journal;
+use aggregators::Aggregator;
+use config::Counter;
+use file;
+use header;
+use header::Header;
+use math;
+use vec;
+
+/// Histogram kept in memory and shared by all worker threads, which increment its pixels
+/// atomically instead of sending their points to an aggregator thread.
+///
+/// Clones refer to the same histogram. It is written to disk on `flush` and once the last clone
+/// is dropped.
+#[derive(Clone)]
+pub struct SharedAggregator {
+    histogram: Arc<Histogram>,
+}
+
+struct Histogram {
+    width: u64,
+    height: u64,
+    min: Complex64,
+    max: Complex64,
+
+    counter: Counter,
+    data: Pixels,
+    storage: Mutex<Storage>,
+}
+
+/// Pixel counts with the width of the counters of the file.
+enum Pixels {
+    U32(Vec<AtomicU32>),
+    U64(Vec<AtomicU64>),
+}
+
+struct Storage {
+    file: File,
+    file_buffer_size: usize,
+    journal: Option<Journal>,
+}
+
+impl SharedAggregator {
+    pub fn create(
+        file_name: &str,
+        header: Header,
+        file_buffer_size: usize,
+    ) -> io::Result<SharedAggregator> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .truncate(true)
+            .create(true)
+            .open(file_name)?;
+        header.write(&mut file)?;
+
+        let aggregator = SharedAggregator::with_file(file, &header, file_buffer_size);
+        aggregator.histogram.flush()?;
+
+        Ok(aggregator)
+    }
+
+    /// Reopens a file written by a previous run and keeps adding to it.
+    pub fn open(
+        file_name: &str,
+        header: Header,
+        file_buffer_size: usize,
+    ) -> io::Result<SharedAggregator> {
+        let mut file = OpenOptions::new().read(true).write(true).open(file_name)?;
+
+        match Header::read(&mut file)? {
+            Some(ref existing) if existing.is_compatible(&header) => {}
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} does not match the configured image", file_name),
+                ))
+            }
+        }
+
+        let aggregator = SharedAggregator::with_file(file, &header, file_buffer_size);
+        aggregator.load()?;
+
+        Ok(aggregator)
+    }
+
+    fn with_file(file: File, header: &Header, file_buffer_size: usize) -> SharedAggregator {
+        SharedAggregator {
+            histogram: Arc::new(Histogram {
+                width: header.width,
+                height: header.height,
+                min: header.min,
+                max: header.max,
+
+                counter: header.counter,
+                data: Pixels::new(header.counter, header.pixels() as usize),
+                storage: Mutex::new(Storage {
+                    file,
+                    file_buffer_size,
+                    journal: None,
+                }),
+            }),
+        }
+    }
+
+    /// Undoes all writes made after the checkpoint at `samples_done`, using the journal left
+    /// behind by an interrupted run.
+    pub fn rollback(&mut self, journal: &str, samples_done: usize) -> io::Result<bool> {
+        let restored = {
+            let mut storage = self.histogram.storage.lock().unwrap();
+            let file_buffer_size = storage.file_buffer_size;
+            Journal::rollback(
+                journal,
+                &mut storage.file,
+                header::SIZE,
+                self.histogram.counter,
+                file_buffer_size,
+                samples_done as u64,
+            )?
+        };
+
+        if restored {
+            self.load()?;
+        }
+        Ok(restored)
+    }
+
+    /// Journals all further writes, so the file can be rolled back to the checkpoint at
+    /// `samples_done`.
+    pub fn enable_journal(&mut self, journal: &str, samples_done: usize) -> io::Result<()> {
+        let mut storage = self.histogram.storage.lock().unwrap();
+        let buffers = self.histogram.data.len() / storage.file_buffer_size + 1;
+        storage.journal = Some(Journal::create(journal, buffers, samples_done as u64)?);
+        Ok(())
+    }
+
+    fn load(&self) -> io::Result<()> {
+        let mut storage = self.histogram.storage.lock().unwrap();
+        let file_buffer_size = storage.file_buffer_size;
+        let mut buffer = vec::filled_with(0u64, file_buffer_size);
+
+        let pixels = self.histogram.data.len();
+        for start in (0..pixels).step_by(file_buffer_size) {
+            let buffer = &mut buffer[..file_buffer_size.min(pixels - start)];
+            file::read_counts(
+                &mut storage.file,
+                header::SIZE,
+                self.histogram.counter,
+                start as u64,
+                buffer,
+            )?;
+            self.histogram.data.store(start, buffer);
+        }
+
+        Ok(())
+    }
+}
+impl Histogram {
+    fn flush(&self) -> io::Result<()> {
+        let mut storage = self.storage.lock().unwrap();
+        let Storage {
+            ref mut file,
+            file_buffer_size,
+            ref mut journal,
+        } = *storage;
+
+        let mut previous = vec::filled_with(0u64, file_buffer_size);
+        let mut current = vec::filled_with(0u64, file_buffer_size);
+
+        let pixels = self.data.len();
+        for (i, start) in (0..pixels).step_by(file_buffer_size).enumerate() {
+            let length = file_buffer_size.min(pixels - start);
+            let location = start as u64;
+            let current = &mut current[..length];
+            self.data.load(start, current);
+
+            if let Some(ref mut journal) = *journal {
+                let previous = &mut previous[..length];
+                file::read_counts(file, header::SIZE, self.counter, location, previous)?;
+                if previous == current {
+                    continue;
+                }
+                journal.save(i, previous)?;
+            }
+
+            file::write_counts(file, header::SIZE, self.counter, location, current)?;
+        }
+
+        file.sync_data()
+    }
+}
+impl Pixels {
+    fn new(counter: Counter, pixels: usize) -> Pixels {
+        match counter {
+            Counter::U32 => Pixels::U32((0..pixels).map(|_| AtomicU32::new(0)).collect()),
+            Counter::U64 => Pixels::U64((0..pixels).map(|_| AtomicU64::new(0)).collect()),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Pixels::U32(data) => data.len(),
+            Pixels::U64(data) => data.len(),
+        }
+    }
+
+    fn increment(&self, pixel: usize) {
+        // Wrapping around is rare enough to undo it afterwards, instead of paying for a compare
+        // and swap on every increment.
+        match self {
+            Pixels::U32(data) => {
+                if data[pixel].fetch_add(1, Ordering::Relaxed) == u32::MAX {
+                    data[pixel].store(u32::MAX, Ordering::Relaxed);
+                }
+            }
+            Pixels::U64(data) => {
+                if data[pixel].fetch_add(1, Ordering::Relaxed) == u64::MAX {
+                    data[pixel].store(u64::MAX, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+
+    fn load(&self, start: usize, values: &mut [u64]) {
+        match self {
+            Pixels::U32(data) => {
+                for (value, pixel) in values.iter_mut().zip(&data[start..]) {
+                    *value = u64::from(pixel.load(Ordering::Relaxed));
+                }
+            }
+            Pixels::U64(data) => {
+                for (value, pixel) in values.iter_mut().zip(&data[start..]) {
+                    *value = pixel.load(Ordering::Relaxed);
+                }
+            }
+        }
+    }
+
+    fn store(&self, start: usize, values: &[u64]) {
+        match self {
+            Pixels::U32(data) => {
+                for (value, pixel) in values.iter().zip(&data[start..]) {
+                    pixel.store(*value as u32, Ordering::Relaxed);
+                }
+            }
+            Pixels::U64(data) => {
+                for (value, pixel) in values.iter().zip(&data[start..]) {
+                    pixel.store(*value, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+}
+impl Aggregator for SharedAggregator {
+    fn aggregate(&mut self, c: Complex64) {
+        let histogram = &self.histogram;
+
+        if math::complex_between(histogram.min, c, histogram.max) {
+            let (x, y) = math::complex_to_image(
+                c,
+                histogram.min,
+                histogram.max,
+                histogram.width,
+                histogram.height,
+            );
+
+            if x < histogram.width && y < histogram.height {
+                histogram.data.increment((y * histogram.width + x) as usize);
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.histogram.flush()
+    }
+
+    fn commit(&mut self, samples_done: usize) -> io::Result<()> {
+        match self.histogram.storage.lock().unwrap().journal {
+            Some(ref mut journal) => journal.reset(samples_done as u64),
+            None => Ok(()),
+        }
+    }
+}
+impl Drop for Histogram {
+    fn drop(&mut self) {
+        self.flush().expect("Error while writing histogram");
+    }
+}
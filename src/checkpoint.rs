@@ -0,0 +1,52 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+
+use serde_json;
+
+use header::BandStatistics;
+use report::EscapeReport;
+
+/// Progress of an interrupted `generate` run.
+///
+/// A checkpoint is only written while all workers are between sections and after every
+/// aggregator flushed its buffers, so the histogram files contain exactly `samples_done` samples.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Checkpoint {
+    pub samples: usize,
+    pub samples_done: usize,
+
+    pub files: Vec<String>,
+    /// Master seed of the run, which determines the samples of all remaining sections.
+    pub seed: u64,
+    /// Band statistics of the samples done, in the order of `files`. Missing in checkpoints of
+    /// older versions.
+    #[serde(default)]
+    pub bands: Vec<BandStatistics>,
+    /// Escape iterations of the samples done, if a report was requested.
+    #[serde(default)]
+    pub report: Option<EscapeReport>,
+}
+impl Checkpoint {
+    pub fn load(path: &str) -> io::Result<Checkpoint> {
+        let mut contents = String::new();
+        File::open(path)?.read_to_string(&mut contents)?;
+
+        serde_json::from_str(&contents).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+
+    /// Replaces the checkpoint file atomically, so an interruption never leaves a partial file.
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+
+        let temporary = format!("{}.tmp", path);
+        {
+            let mut file = File::create(&temporary)?;
+            file.write_all(contents.as_bytes())?;
+            file.sync_all()?;
+        }
+        fs::rename(&temporary, path)
+    }
+}
@@ -0,0 +1,501 @@
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+use num::complex::Complex64;
+use format::Format;
+use formulas::FormulaConfig;
+use image::Layer;
+use resample::{Filter, Output};
+use tone_map::{Operator, ToneMap};
+use serde_json;
+use toml;
+
+const DEFAULT_PRESET: &str = include_str!("../presets/default.toml");
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub formula: FormulaConfig,
+    pub initial_z: Complex64,
+
+    pub bailout_min: Complex64,
+    pub bailout_max: Complex64,
+
+    pub scan_min: Complex64,
+    pub scan_max: Complex64,
+    pub samples: usize,
+    pub sample_section: usize,
+    #[serde(default)]
+    pub sampler: Sampler,
+    /// Master seed for all random numbers. Runs with the same seed, samples and threads write
+    /// identical files. Without a seed, a random one is chosen and printed.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Samples only the upper half plane and mirrors all orbits at the real axis, which halves
+    /// the work. Ignored for formulas or initial values that are not symmetric.
+    #[serde(default)]
+    pub symmetry: bool,
+
+    pub check_iterations: usize,
+    pub images: Vec<ImageConfig>,
+
+    pub eta_section: usize,
+    pub eta_time: u64,
+
+    pub threads: usize,
+    #[serde(default)]
+    pub aggregation: Aggregation,
+    pub channel_buffer: usize,
+    pub thread_buffer: usize,
+
+    /// Width of the pixel counters in newly created histograms.
+    #[serde(default)]
+    pub counter: Counter,
+    pub file_buffer_size: usize,
+    pub pixel_buffer_cutoff_size: usize,
+
+    /// Seconds between checkpoints; 0 disables checkpointing.
+    #[serde(default)]
+    pub checkpoint_interval: u64,
+    #[serde(default = "default_checkpoint_file")]
+    pub checkpoint_file: String,
+    /// Writes the distribution of escape iterations of all samples to this file at the end of
+    /// `generate`, as JSON for a .json extension and as CSV otherwise. Under the Metropolis
+    /// sampler, samples are weighted by how often their orbits were recorded.
+    #[serde(default)]
+    pub report_file: Option<String>,
+
+    pub image_file_name: String,
+    /// Format of the rendered images. Band images get its extension appended to `file_name`,
+    /// the composite has the extension of `image_file_name` replaced.
+    #[serde(default)]
+    pub format: Format,
+    /// Resamples the histograms to this size when rendering.
+    #[serde(default)]
+    pub output: Option<Output>,
+}
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ImageConfig {
+    pub min_iterations: usize,
+    pub max_iterations: usize,
+
+    pub width: u64,
+    pub height: u64,
+
+    pub min: Complex64,
+    pub max: Complex64,
+
+    /// Which orbits are recorded. With `bounded`, the band selects the part of the orbit of
+    /// points that did not escape within `check_iterations` (the anti-Buddhabrot).
+    #[serde(default)]
+    pub orbits: Orbits,
+
+    /// Brightness of the rendered band, also used for its layer.
+    #[serde(default)]
+    pub tone_map: ToneMap,
+    /// Adds this band to the RGB composite written to `image_file_name`.
+    #[serde(default)]
+    pub layer: Option<Layer>,
+
+    pub file_name: String,
+}
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Orbits {
+    #[default]
+    Escaping,
+    Bounded,
+}
+/// How orbit points get into the histograms.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Aggregation {
+    /// Workers send their points to one aggregator thread per image, which sorts them into file
+    /// buffers. Needs little memory, so it also works for histograms larger than RAM.
+    #[default]
+    Channel,
+    /// Workers increment a histogram kept in memory directly. Faster, but every image has to fit
+    /// into RAM.
+    Shared,
+}
+/// Width of the pixel counters stored in a histogram. Counts stop at the largest value instead
+/// of wrapping around.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Counter {
+    #[default]
+    U32,
+    U64,
+}
+impl Counter {
+    pub fn bytes(self) -> usize {
+        match self {
+            Counter::U32 => 4,
+            Counter::U64 => 8,
+        }
+    }
+
+    /// The count at which a pixel saturates.
+    pub fn ceiling(self) -> u64 {
+        match self {
+            Counter::U32 => u64::from(u32::MAX),
+            Counter::U64 => u64::MAX,
+        }
+    }
+}
+/// How the locations `c` are chosen within `scan_min`..`scan_max`.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Sampler {
+    #[default]
+    Uniform,
+    /// Metropolis–Hastings sampling, which concentrates on locations whose orbits pass through
+    /// the images. Mutations move `c` by a normal distributed offset with standard deviation
+    /// `mutation_radius`; with `large_step_probability` a new uniform location is tried instead.
+    /// Before the run, `pilot_samples` uniform samples estimate the mean contribution the
+    /// histograms are normalized by. Its relative error is about that of the band totals of a
+    /// uniform run with as many samples.
+    Metropolis {
+        mutation_radius: f64,
+        large_step_probability: f64,
+        #[serde(default = "default_pilot_samples")]
+        pilot_samples: usize,
+    },
+}
+impl Config {
+    pub fn default_preset() -> Config {
+        Config::from_toml(DEFAULT_PRESET).expect("Bundled default preset is invalid")
+    }
+
+    /// Loads a config file. Files ending in `.json` are parsed as JSON, everything else as TOML.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, ConfigError> {
+        let path = path.as_ref();
+
+        let mut contents = String::new();
+        File::open(path)?.read_to_string(&mut contents)?;
+
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => Config::from_json(&contents),
+            _ => Config::from_toml(&contents),
+        }
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Config, ConfigError> {
+        let config: Config = toml::from_str(contents)?;
+        config.validate()?;
+        Ok(config)
+    }
+    pub fn from_json(contents: &str) -> Result<Config, ConfigError> {
+        let config: Config = serde_json::from_str(contents)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Replaces the configured images by one image per iteration band, using the first image as
+    /// template for size and location.
+    pub fn set_bands(&mut self, bands: &[(usize, usize)]) {
+        let template = self.images[0].clone();
+
+        self.images = bands
+            .iter()
+            .map(|&(min_iterations, max_iterations)| ImageConfig {
+                min_iterations,
+                max_iterations,
+                file_name: format!("image-{}-{}.mbh", min_iterations, max_iterations),
+                layer: None,
+                ..template.clone()
+            }).collect();
+    }
+
+    /// Moves all output files into the given directory.
+    pub fn set_output_dir<P: AsRef<Path>>(&mut self, dir: P) {
+        let dir = dir.as_ref();
+
+        for image in &mut self.images {
+            image.file_name = in_dir(dir, &image.file_name);
+        }
+        self.image_file_name = in_dir(dir, &self.image_file_name);
+        self.checkpoint_file = in_dir(dir, &self.checkpoint_file);
+        self.report_file = self.report_file.as_ref().map(|file| in_dir(dir, file));
+    }
+
+    pub fn estimated_memory_usage(&self) -> usize {
+        if self.aggregation == Aggregation::Shared {
+            return self.threads * self.thread_buffer * 2 * 8 * self.images.len()
+                + self
+                    .images
+                    .iter()
+                    .map(|image| {
+                        image.width as usize * image.height as usize * self.counter.bytes()
+                    })
+                    .sum::<usize>();
+        }
+
+        (self.threads + self.channel_buffer) * self.thread_buffer * 2 * 8 * self.images.len()
+            + (self
+                .images
+                .iter()
+                .map(|image| image.width as usize * image.height as usize)
+                .sum::<usize>()
+                / self.file_buffer_size
+                + 1)
+                * self.pixel_buffer_cutoff_size
+                * 4
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        match self.formula {
+            FormulaConfig::Power { exponent } if exponent < 2 => {
+                return Err(ConfigError::invalid("formula.exponent", "must be at least 2"))
+            }
+            FormulaConfig::RealPower { exponent } if exponent.is_nan() || exponent <= 1.0 => {
+                return Err(ConfigError::invalid("formula.exponent", "must be greater than 1"))
+            }
+            _ => {}
+        }
+        if let Sampler::Metropolis {
+            mutation_radius,
+            large_step_probability,
+            pilot_samples,
+        } = self.sampler
+        {
+            if !(mutation_radius > 0.0 && mutation_radius.is_finite()) {
+                return Err(ConfigError::invalid(
+                    "sampler.mutation_radius",
+                    "must be greater than zero",
+                ));
+            }
+            if !(large_step_probability > 0.0 && large_step_probability <= 1.0) {
+                return Err(ConfigError::invalid(
+                    "sampler.large_step_probability",
+                    "must be greater than zero and at most 1",
+                ));
+            }
+            check_positive("sampler.pilot_samples", pilot_samples)?;
+        }
+        check_window("bailout_min", self.bailout_min, self.bailout_max)?;
+        check_window("scan_min", self.scan_min, self.scan_max)?;
+        check_positive("samples", self.samples)?;
+        check_positive("sample_section", self.sample_section)?;
+        check_positive("check_iterations", self.check_iterations)?;
+        check_positive("eta_section", self.eta_section)?;
+        check_positive("threads", self.threads)?;
+        check_positive("channel_buffer", self.channel_buffer)?;
+        check_positive("thread_buffer", self.thread_buffer)?;
+        check_positive("file_buffer_size", self.file_buffer_size)?;
+        check_positive("pixel_buffer_cutoff_size", self.pixel_buffer_cutoff_size)?;
+        if self.checkpoint_file.is_empty() {
+            return Err(ConfigError::invalid("checkpoint_file", "must not be empty"));
+        }
+        if self.report_file.as_ref().is_some_and(|file| file.is_empty()) {
+            return Err(ConfigError::invalid("report_file", "must not be empty"));
+        }
+
+        if let Some(ref output) = self.output {
+            check_positive("output.width", output.width)?;
+            check_positive("output.height", output.height)?;
+            match output.filter {
+                Filter::Gaussian { sigma } if !(sigma > 0.0 && sigma.is_finite()) => {
+                    return Err(ConfigError::invalid(
+                        "output.filter.sigma",
+                        "must be greater than zero",
+                    ))
+                }
+                Filter::Lanczos { a: 0 } => {
+                    return Err(ConfigError::invalid(
+                        "output.filter.a",
+                        "must be greater than zero",
+                    ))
+                }
+                _ => {}
+            }
+        }
+
+        if self.images.is_empty() {
+            return Err(ConfigError::invalid("images", "at least one image is required"));
+        }
+        for (i, image) in self.images.iter().enumerate() {
+            image
+                .validate(self.check_iterations)
+                .map_err(|error| error.within(&format!("images[{}]", i)))?;
+        }
+
+        Ok(())
+    }
+}
+impl ImageConfig {
+    pub fn validate(&self, check_iterations: usize) -> Result<(), ConfigError> {
+        if self.min_iterations >= self.max_iterations {
+            return Err(ConfigError::invalid(
+                "max_iterations",
+                "must be greater than min_iterations",
+            ));
+        }
+        if self.max_iterations > check_iterations {
+            return Err(ConfigError::invalid(
+                "max_iterations",
+                &format!("must not exceed check_iterations ({})", check_iterations),
+            ));
+        }
+        check_positive("width", self.width as usize)?;
+        check_positive("height", self.height as usize)?;
+        check_window("min", self.min, self.max)?;
+        match self.tone_map.operator {
+            Operator::Gamma { gamma } if !(gamma > 0.0 && gamma.is_finite()) => {
+                return Err(ConfigError::invalid(
+                    "tone_map.operator.gamma",
+                    "must be greater than zero",
+                ))
+            }
+            Operator::Exponential { k } if !(k > 0.0 && k.is_finite()) => {
+                return Err(ConfigError::invalid(
+                    "tone_map.operator.k",
+                    "must be greater than zero",
+                ))
+            }
+            _ => {}
+        }
+        for &(field, percentile) in &[
+            ("tone_map.black_percentile", self.tone_map.black_percentile),
+            ("tone_map.white_percentile", self.tone_map.white_percentile),
+        ] {
+            if let Some(percentile) = percentile {
+                if !(0.0..=100.0).contains(&percentile) {
+                    return Err(ConfigError::invalid(field, "must be between 0 and 100"));
+                }
+            }
+        }
+        for &(field, density) in &[
+            ("tone_map.black_density", self.tone_map.black_density),
+            ("tone_map.white_density", self.tone_map.white_density),
+        ] {
+            if let Some(density) = density {
+                if !(density >= 0.0 && density.is_finite()) {
+                    return Err(ConfigError::invalid(field, "must not be negative"));
+                }
+            }
+        }
+        if let (Some(black_density), Some(white_density)) =
+            (self.tone_map.black_density, self.tone_map.white_density)
+        {
+            if white_density <= black_density {
+                return Err(ConfigError::invalid(
+                    "tone_map.white_density",
+                    "must be greater than black_density",
+                ));
+            }
+        }
+        if let Some(white_point) = self.tone_map.white_point {
+            if white_point <= self.tone_map.black_point {
+                return Err(ConfigError::invalid(
+                    "tone_map.white_point",
+                    "must be greater than black_point",
+                ));
+            }
+        }
+        if let Some(ref layer) = self.layer {
+            if layer.color.iter().any(|component| component.is_nan() || *component < 0.0) {
+                return Err(ConfigError::invalid("layer.color", "must not be negative"));
+            }
+            if layer.weight.is_nan() || layer.weight < 0.0 {
+                return Err(ConfigError::invalid("layer.weight", "must not be negative"));
+            }
+        }
+        if self.file_name.is_empty() {
+            return Err(ConfigError::invalid("file_name", "must not be empty"));
+        }
+
+        Ok(())
+    }
+}
+
+fn default_checkpoint_file() -> String {
+    "checkpoint.json".to_owned()
+}
+
+fn default_pilot_samples() -> usize {
+    10_000_000
+}
+
+fn in_dir(dir: &Path, file_name: &str) -> String {
+    let file_name = Path::new(file_name).file_name().unwrap_or_default();
+    dir.join(file_name).to_string_lossy().into_owned()
+}
+
+fn check_positive(field: &str, value: usize) -> Result<(), ConfigError> {
+    if value == 0 {
+        Err(ConfigError::invalid(field, "must be greater than zero"))
+    } else {
+        Ok(())
+    }
+}
+fn check_window(field: &str, min: Complex64, max: Complex64) -> Result<(), ConfigError> {
+    if min.re < max.re && min.im < max.im {
+        Ok(())
+    } else {
+        Err(ConfigError::invalid(
+            field,
+            "real and imaginary parts must be smaller than the corresponding maximum",
+        ))
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Toml(toml::de::Error),
+    Json(serde_json::Error),
+    Invalid { field: String, message: String },
+}
+impl ConfigError {
+    fn invalid(field: &str, message: &str) -> ConfigError {
+        ConfigError::Invalid {
+            field: field.to_owned(),
+            message: message.to_owned(),
+        }
+    }
+
+    fn within(self, parent: &str) -> ConfigError {
+        match self {
+            ConfigError::Invalid { field, message } => ConfigError::Invalid {
+                field: format!("{}.{}", parent, field),
+                message,
+            },
+            error => error,
+        }
+    }
+}
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(error) => write!(f, "Could not read config: {}", error),
+            ConfigError::Toml(error) => write!(f, "Could not parse config: {}", error),
+            ConfigError::Json(error) => write!(f, "Could not parse config: {}", error),
+            ConfigError::Invalid { field, message } => {
+                write!(f, "Invalid config value {}: {}", field, message)
+            }
+        }
+    }
+}
+impl error::Error for ConfigError {}
+impl From<io::Error> for ConfigError {
+    fn from(error: io::Error) -> ConfigError {
+        ConfigError::Io(error)
+    }
+}
+impl From<toml::de::Error> for ConfigError {
+    fn from(error: toml::de::Error) -> ConfigError {
+        ConfigError::Toml(error)
+    }
+}
+impl From<serde_json::Error> for ConfigError {
+    fn from(error: serde_json::Error) -> ConfigError {
+        ConfigError::Json(error)
+    }
+}
@@ -9,13 +9,18 @@ pub struct ETA {
 }
 impl ETA {
     pub fn new(total: usize, section_total: usize, timeout: u64) -> ETA {
+        ETA::resume(total, 0, section_total, timeout)
+    }
+    /// Creates an ETA for a run that already completed `initial` of `total`.
+    pub fn resume(total: usize, initial: usize, section_total: usize, timeout: u64) -> ETA {
         let eta_store = Arc::new(ETAStore {
             start: Instant::now(),
             timeout,
             total,
+            initial,
 
-            last_current: AtomicUsize::new(0),
-            current: AtomicUsize::new(0),
+            last_current: AtomicUsize::new(initial),
+            current: AtomicUsize::new(initial),
         });
         ETAStore::run_thread(eta_store.clone());
 
@@ -52,7 +57,8 @@ struct ETAStore {
     start: Instant,
     timeout: u64,
     total: usize,
-    
+    initial: usize,
+
     last_current: AtomicUsize,
     current: AtomicUsize,
 }
@@ -70,7 +76,8 @@ impl ETAStore {
             + duration.subsec_millis() as f64 * 1e-3
             + duration.subsec_micros() as f64 * 1e-6;
 
-        let estimated_left = (duration * (self.total as f64 / current as f64) - duration) as u64;
+        let done = (current - self.initial) as f64;
+        let estimated_left = (duration * (self.total.saturating_sub(self.initial) as f64 / done) - duration) as u64;
         println!(
             "ETA: {}h{:02}m{:02}s; {} / {}; {:.5}%; {:.2} samples/s; {} last frame",
             estimated_left / (60 * 60),
@@ -79,7 +86,7 @@ impl ETAStore {
             current,
             self.total,
             (current as f64 / self.total as f64) * 100.0,
-            current as f64 / duration,
+            done / duration,
             current - last_current,
         );
 
@@ -1,29 +1,117 @@
+use std::borrow::Cow;
 use std::fs::File;
 use std::io;
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::mem;
 use std::slice;
 
-pub fn read_u32(file: &mut File, location: u64, buffer: &mut [u32]) -> io::Result<usize> {
-    file.seek(SeekFrom::Start(location * mem::size_of::<u32>() as u64))?;
+use config::Counter;
+use vec;
+
+// Values are stored in little endian; `offset` is the byte position of the first value.
+// Reading past the end of the file fails with `UnexpectedEof`.
+pub fn read_u32(file: &mut File, offset: u64, location: u64, buffer: &mut [u32]) -> io::Result<()> {
+    file.seek(SeekFrom::Start(offset + location * mem::size_of::<u32>() as u64))?;
+
+    {
+        let buffer = unsafe {
+            slice::from_raw_parts_mut(buffer.as_ptr() as *mut u8, mem::size_of_val(buffer))
+        };
+        file.read_exact(buffer)?;
+    }
+
+    for value in buffer.iter_mut() {
+        *value = u32::from_le(*value);
+    }
+
+    Ok(())
+}
+
+pub fn write_u32(file: &mut File, offset: u64, location: u64, buffer: &[u32]) -> io::Result<()> {
+    file.seek(SeekFrom::Start(offset + location * mem::size_of::<u32>() as u64))?;
+
+    let buffer: Cow<[u32]> = if cfg!(target_endian = "little") {
+        Cow::Borrowed(buffer)
+    } else {
+        Cow::Owned(buffer.iter().map(|value| value.to_le()).collect())
+    };
 
     let buffer = unsafe {
-        slice::from_raw_parts_mut(
-            buffer.as_ptr() as *mut u8,
-            buffer.len() * mem::size_of::<u32>(),
-        )
+        slice::from_raw_parts(buffer.as_ptr() as *const u8, mem::size_of_val(&buffer[..]))
     };
-    file.read(buffer)
+    file.write_all(buffer)
 }
 
-pub fn write_u32(file: &mut File, location: u64, buffer: &[u32]) -> io::Result<usize> {
-    file.seek(SeekFrom::Start(location * mem::size_of::<u32>() as u64))?;
+pub fn read_u64(file: &mut File, offset: u64, location: u64, buffer: &mut [u64]) -> io::Result<()> {
+    file.seek(SeekFrom::Start(offset + location * mem::size_of::<u64>() as u64))?;
+
+    {
+        let buffer = unsafe {
+            slice::from_raw_parts_mut(buffer.as_ptr() as *mut u8, mem::size_of_val(buffer))
+        };
+        file.read_exact(buffer)?;
+    }
+
+    for value in buffer.iter_mut() {
+        *value = u64::from_le(*value);
+    }
+
+    Ok(())
+}
+
+pub fn write_u64(file: &mut File, offset: u64, location: u64, buffer: &[u64]) -> io::Result<()> {
+    file.seek(SeekFrom::Start(offset + location * mem::size_of::<u64>() as u64))?;
+
+    let buffer: Cow<[u64]> = if cfg!(target_endian = "little") {
+        Cow::Borrowed(buffer)
+    } else {
+        Cow::Owned(buffer.iter().map(|value| value.to_le()).collect())
+    };
 
     let buffer = unsafe {
-        slice::from_raw_parts(
-            buffer.as_ptr() as *const u8,
-            buffer.len() * mem::size_of::<u32>(),
-        )
+        slice::from_raw_parts(buffer.as_ptr() as *const u8, mem::size_of_val(&buffer[..]))
     };
-    file.write(buffer)
+    file.write_all(buffer)
+}
+
+/// Reads pixel counts stored with the given counter width; `location` counts pixels.
+pub fn read_counts(
+    file: &mut File,
+    offset: u64,
+    counter: Counter,
+    location: u64,
+    buffer: &mut [u64],
+) -> io::Result<()> {
+    match counter {
+        Counter::U32 => {
+            let mut narrow = vec::filled_with(0u32, buffer.len());
+            read_u32(file, offset, location, &mut narrow)?;
+            for (value, narrow) in buffer.iter_mut().zip(&narrow) {
+                *value = u64::from(*narrow);
+            }
+            Ok(())
+        }
+        Counter::U64 => read_u64(file, offset, location, buffer),
+    }
+}
+
+/// Writes pixel counts with the given counter width, saturating those that do not fit.
+pub fn write_counts(
+    file: &mut File,
+    offset: u64,
+    counter: Counter,
+    location: u64,
+    buffer: &[u64],
+) -> io::Result<()> {
+    match counter {
+        Counter::U32 => {
+            let narrow = buffer.iter().map(|value| narrow(*value)).collect::<Vec<_>>();
+            write_u32(file, offset, location, &narrow)
+        }
+        Counter::U64 => write_u64(file, offset, location, buffer),
+    }
+}
+
+fn narrow(value: u64) -> u32 {
+    value.min(u64::from(u32::MAX)) as u32
 }
@@ -0,0 +1,266 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use image::{Image, Samples};
+use png_stream::PngStream;
+
+/// File format of rendered images.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    /// 8 bit PNG.
+    #[default]
+    Png,
+    /// 16 bit PNG.
+    Png16,
+    /// 16 bit uncompressed TIFF.
+    Tiff16,
+    /// Portable float map with 32 bit floats. Composites are not clipped, so layers adding up to
+    /// more than 1 keep their highlights.
+    Pfm,
+}
+
+/// Sample type of an `Image`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Depth {
+    Eight,
+    Sixteen,
+    Float,
+}
+
+impl Format {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Png | Format::Png16 => "png",
+            Format::Tiff16 => "tiff",
+            Format::Pfm => "pfm",
+        }
+    }
+
+    pub fn depth(self) -> Depth {
+        match self {
+            Format::Png => Depth::Eight,
+            Format::Png16 | Format::Tiff16 => Depth::Sixteen,
+            Format::Pfm => Depth::Float,
+        }
+    }
+
+    /// Picks the format for a file from its extension and the depth of the image.
+    pub fn for_path(path: &str, depth: Depth) -> io::Result<Format> {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map_or(String::new(), |extension| extension.to_ascii_lowercase());
+
+        match (&*extension, depth) {
+            ("png", Depth::Eight) => Ok(Format::Png),
+            ("png", Depth::Sixteen) => Ok(Format::Png16),
+            ("tif", Depth::Sixteen) | ("tiff", Depth::Sixteen) => Ok(Format::Tiff16),
+            ("pfm", Depth::Float) => Ok(Format::Pfm),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Can not save {:?} samples to {}", depth, path),
+            )),
+        }
+    }
+}
+
+/// Writes an image of any format row by row, so images larger than memory can be saved.
+pub struct ImageStream {
+    writer: Writer,
+
+    format: Format,
+    channels: usize,
+    width: usize,
+    height: usize,
+    rows_written: usize,
+}
+
+enum Writer {
+    Png(Box<PngStream>),
+    /// The image data is written as a single strip right after the header, followed by the IFD.
+    Tiff(BufWriter<File>),
+    /// Rows are stored bottom to top, so every strip is written at its final position.
+    Pfm { file: File, header_length: u64 },
+}
+
+/// Bytes of the TIFF header and of the IFD including the bits per sample of RGB images.
+const TIFF_HEADER: u64 = 8;
+const TIFF_ENTRIES: u16 = 10;
+const TIFF_IFD: u64 = 2 + TIFF_ENTRIES as u64 * 12 + 4 + 3 * 2;
+
+impl ImageStream {
+    pub fn create(
+        path: &str,
+        format: Format,
+        width: usize,
+        height: usize,
+        rgb: bool,
+    ) -> io::Result<ImageStream> {
+        let channels = if rgb { 3 } else { 1 };
+
+        let writer = match format {
+            Format::Png | Format::Png16 => Writer::Png(Box::new(PngStream::create(
+                path,
+                width,
+                height,
+                rgb,
+                format == Format::Png16,
+            )?)),
+            Format::Tiff16 => {
+                let length = (width * height * channels * 2) as u64;
+                if TIFF_HEADER + length + TIFF_IFD > u64::from(u32::MAX) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("{}x{} is too large for a TIFF", width, height),
+                    ));
+                }
+
+                let mut file = BufWriter::new(File::create(path)?);
+                file.write_all(b"II")?;
+                file.write_all(&42u16.to_le_bytes())?;
+                file.write_all(&((TIFF_HEADER + length) as u32).to_le_bytes())?;
+                Writer::Tiff(file)
+            }
+            Format::Pfm => {
+                let header = format!(
+                    "{}\n{} {}\n-1.0\n",
+                    if rgb { "PF" } else { "Pf" },
+                    width,
+                    height
+                );
+                let mut file = File::create(path)?;
+                file.write_all(header.as_bytes())?;
+                let header_length = header.len() as u64;
+                file.set_len(header_length + (width * height * channels * 4) as u64)?;
+                Writer::Pfm {
+                    file,
+                    header_length,
+                }
+            }
+        };
+
+        Ok(ImageStream {
+            writer,
+            format,
+            channels,
+            width,
+            height,
+            rows_written: 0,
+        })
+    }
+
+    /// Appends the rows of an image of the same width, depth and channels.
+    pub fn write(&mut self, image: &Image) -> io::Result<()> {
+        if image.width() != self.width
+            || image.channels() != self.channels
+            || image.depth() != self.format.depth()
+            || self.rows_written + image.height() > self.height
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Image data does not match the image file",
+            ));
+        }
+
+        match (&mut self.writer, image.samples()) {
+            (Writer::Png(png), Samples::Eight(data)) => png.write_rows(data)?,
+            (Writer::Png(png), Samples::Sixteen(data)) => {
+                let bytes = data
+                    .iter()
+                    .flat_map(|value| value.to_be_bytes())
+                    .collect::<Vec<_>>();
+                png.write_rows(&bytes)?
+            }
+            (Writer::Tiff(file), Samples::Sixteen(data)) => {
+                for value in data {
+                    file.write_all(&value.to_le_bytes())?;
+                }
+            }
+            (
+                Writer::Pfm {
+                    file,
+                    header_length,
+                },
+                Samples::Float(data),
+            ) => {
+                let row_length = self.width * self.channels;
+                let mut bytes = Vec::with_capacity(data.len() * 4);
+                for row in data.chunks(row_length).rev() {
+                    for value in row {
+                        bytes.extend_from_slice(&value.to_le_bytes());
+                    }
+                }
+
+                let first = self.height - self.rows_written - image.height();
+                file.seek(SeekFrom::Start(
+                    *header_length + (first * row_length * 4) as u64,
+                ))?;
+                file.write_all(&bytes)?;
+            }
+            _ => unreachable!(),
+        }
+
+        self.rows_written += image.height();
+        Ok(())
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        if self.rows_written < self.height {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} rows of the image are missing",
+                    self.height - self.rows_written
+                ),
+            ));
+        }
+
+        match self.writer {
+            Writer::Png(png) => png.finish(),
+            Writer::Tiff(mut file) => {
+                let length = (self.width * self.height * self.channels * 2) as u32;
+                let bits_per_sample = if self.channels == 1 {
+                    16
+                } else {
+                    // Stored after the IFD, as three values do not fit into the entry.
+                    TIFF_HEADER as u32 + length + TIFF_IFD as u32 - 6
+                };
+                let photometric = if self.channels == 1 { 1 } else { 2 };
+
+                file.write_all(&TIFF_ENTRIES.to_le_bytes())?;
+                for &(tag, short, count, value) in &[
+                    (256, false, 1, self.width as u32),
+                    (257, false, 1, self.height as u32),
+                    (258, true, self.channels as u32, bits_per_sample),
+                    (259, true, 1, 1),
+                    (262, true, 1, photometric),
+                    (273, false, 1, TIFF_HEADER as u32),
+                    (277, true, 1, self.channels as u32),
+                    (278, false, 1, self.height as u32),
+                    (279, false, 1, length),
+                    (284, true, 1, 1),
+                ] {
+                    file.write_all(&(tag as u16).to_le_bytes())?;
+                    file.write_all(&(if short { 3u16 } else { 4u16 }).to_le_bytes())?;
+                    file.write_all(&count.to_le_bytes())?;
+                    if short && count == 1 {
+                        file.write_all(&(value as u16).to_le_bytes())?;
+                        file.write_all(&[0, 0])?;
+                    } else {
+                        file.write_all(&value.to_le_bytes())?;
+                    }
+                }
+                file.write_all(&0u32.to_le_bytes())?;
+                for _ in 0..3 {
+                    file.write_all(&16u16.to_le_bytes())?;
+                }
+
+                file.flush()
+            }
+            Writer::Pfm { mut file, .. } => file.flush(),
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use num::complex::Complex64;
+
+use math;
+use math::CalculateNext;
+use simd;
+use simd::Kernel;
+
+/// An iteration formula `z -> f(z) + c` that can be selected from the config.
+///
+/// Implementations are plain structs holding `c`, so the generic hot loops in `math` are
+/// monomorphized for every formula.
+pub trait Formula: CalculateNext + Clone + Send + 'static {
+    /// Returns the formula for the given `c`.
+    fn with_c(&self, c: Complex64) -> Self;
+
+    /// Whether the orbit of `c` starting at z = 0 is known to stay bounded, so the point can be
+    /// skipped without iterating. Formulas without a cheap test always return `false`.
+    fn is_bounded(&self, c: Complex64) -> bool;
+
+    /// Whether `f(conj z) + conj c = conj(f(z) + c)`. Then the orbit of `conj c` mirrors the
+    /// orbit of `c` at the real axis, as long as the initial z is real.
+    fn conjugate_symmetric(&self) -> bool;
+
+    fn name(&self) -> String;
+
+    /// `math::calculate_bailout_iteration` of every point of `cs`. Formulas with a batched
+    /// kernel override this.
+    fn bailout_iterations(
+        &self,
+        cs: &[Complex64],
+        initial: Complex64,
+        bailout_min: Complex64,
+        bailout_max: Complex64,
+        max_iterations: usize,
+        results: &mut [Option<usize>],
+    ) {
+        for (&c, result) in cs.iter().zip(results.iter_mut()) {
+            *result = math::calculate_bailout_iteration(
+                &mut self.with_c(c),
+                initial,
+                bailout_min,
+                bailout_max,
+                max_iterations,
+            );
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum FormulaConfig {
+    /// z^2 + c
+    #[default]
+    Quadratic,
+    /// z^n + c
+    Power { exponent: u32 },
+    /// z^x + c using the principal branch
+    RealPower { exponent: f64 },
+    /// (|Re z| + i|Im z|)^2 + c
+    BurningShip,
+    /// conj(z)^2 + c, also known as Mandelbar
+    Tricorn,
+    /// |Re z^2| + i Im z^2 + c
+    Celtic,
+    /// z^3 (cos(Re z Im c) + i cos(Re c Im z)) + c
+    Trig,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct Quadratic {
+    c: Complex64,
+}
+impl CalculateNext for Quadratic {
+    fn next(&mut self, z: Complex64) -> Complex64 {
+        z * z + self.c
+    }
+}
+impl Formula for Quadratic {
+    fn with_c(&self, c: Complex64) -> Quadratic {
+        Quadratic { c }
+    }
+    fn is_bounded(&self, c: Complex64) -> bool {
+        math::is_inside_mandelbrot_bulb(c)
+    }
+    fn bailout_iterations(
+        &self,
+        cs: &[Complex64],
+        initial: Complex64,
+        bailout_min: Complex64,
+        bailout_max: Complex64,
+        max_iterations: usize,
+        results: &mut [Option<usize>],
+    ) {
+        simd::quadratic_bailout_iterations(
+            Kernel::detect(),
+            cs,
+            initial,
+            bailout_min,
+            bailout_max,
+            max_iterations,
+            results,
+        );
+    }
+    fn conjugate_symmetric(&self) -> bool {
+        true
+    }
+    fn name(&self) -> String {
+        "z^2 + c".to_owned()
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Power {
+    c: Complex64,
+    exponent: u32,
+    bounded_radius: f64,
+}
+impl Power {
+    pub fn new(exponent: u32) -> Power {
+        let n = f64::from(exponent);
+
+        Power {
+            c: Complex64::new(0.0, 0.0),
+            exponent,
+            // For |c| <= (n - 1) n^(-n / (n - 1)) the disc |z| <= n^(-1 / (n - 1)) is mapped into
+            // itself, so the orbit of 0 never leaves it.
+            bounded_radius: (n - 1.0) * n.powf(-n / (n - 1.0)),
+        }
+    }
+}
+impl CalculateNext for Power {
+    fn next(&mut self, z: Complex64) -> Complex64 {
+        let mut result = Complex64::new(1.0, 0.0);
+        let mut base = z;
+        let mut exponent = self.exponent;
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                result *= base;
+            }
+            base *= base;
+            exponent >>= 1;
+        }
+
+        result + self.c
+    }
+}
+impl Formula for Power {
+    fn with_c(&self, c: Complex64) -> Power {
+        Power { c, ..*self }
+    }
+    fn is_bounded(&self, c: Complex64) -> bool {
+        c.norm_sqr() <= self.bounded_radius * self.bounded_radius
+    }
+    fn conjugate_symmetric(&self) -> bool {
+        true
+    }
+    fn name(&self) -> String {
+        format!("z^{} + c", self.exponent)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct RealPower {
+    c: Complex64,
+    exponent: f64,
+}
+impl RealPower {
+    pub fn new(exponent: f64) -> RealPower {
+        RealPower {
+            c: Complex64::new(0.0, 0.0),
+            exponent,
+        }
+    }
+}
+impl CalculateNext for RealPower {
+    fn next(&mut self, z: Complex64) -> Complex64 {
+        z.powf(self.exponent) + self.c
+    }
+}
+impl Formula for RealPower {
+    fn with_c(&self, c: Complex64) -> RealPower {
+        RealPower { c, ..*self }
+    }
+    // No closed form for the interior of non-integer powers.
+    fn is_bounded(&self, _c: Complex64) -> bool {
+        false
+    }
+    // Holds everywhere except on the branch cut along the negative real axis.
+    fn conjugate_symmetric(&self) -> bool {
+        true
+    }
+    fn name(&self) -> String {
+        format!("z^{} + c", self.exponent)
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct BurningShip {
+    c: Complex64,
+}
+impl CalculateNext for BurningShip {
+    fn next(&mut self, z: Complex64) -> Complex64 {
+        let z = Complex64::new(z.re.abs(), z.im.abs());
+        z * z + self.c
+    }
+}
+impl Formula for BurningShip {
+    fn with_c(&self, c: Complex64) -> BurningShip {
+        BurningShip { c }
+    }
+    // The main body is not a cardioid, there is no cheap interior test.
+    fn is_bounded(&self, _c: Complex64) -> bool {
+        false
+    }
+    // The absolute values fold the lower half plane onto the upper one.
+    fn conjugate_symmetric(&self) -> bool {
+        false
+    }
+    fn name(&self) -> String {
+        "burning ship".to_owned()
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct Tricorn {
+    c: Complex64,
+}
+impl CalculateNext for Tricorn {
+    fn next(&mut self, z: Complex64) -> Complex64 {
+        let z = z.conj();
+        z * z + self.c
+    }
+}
+impl Formula for Tricorn {
+    fn with_c(&self, c: Complex64) -> Tricorn {
+        Tricorn { c }
+    }
+    // The main body is a deltoid whose boundary has no cheap closed form.
+    fn is_bounded(&self, _c: Complex64) -> bool {
+        false
+    }
+    fn conjugate_symmetric(&self) -> bool {
+        true
+    }
+    fn name(&self) -> String {
+        "tricorn".to_owned()
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct Celtic {
+    c: Complex64,
+}
+impl CalculateNext for Celtic {
+    fn next(&mut self, z: Complex64) -> Complex64 {
+        let square = z * z;
+        Complex64::new(square.re.abs(), square.im) + self.c
+    }
+}
+impl Formula for Celtic {
+    fn with_c(&self, c: Complex64) -> Celtic {
+        Celtic { c }
+    }
+    // Taking the absolute value breaks the cardioid, there is no cheap interior test.
+    fn is_bounded(&self, _c: Complex64) -> bool {
+        false
+    }
+    fn conjugate_symmetric(&self) -> bool {
+        true
+    }
+    fn name(&self) -> String {
+        "celtic".to_owned()
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct Trig {
+    c: Complex64,
+}
+impl CalculateNext for Trig {
+    fn next(&mut self, z: Complex64) -> Complex64 {
+        z * z * z * Complex64::new((z.re * self.c.im).cos(), (self.c.re * z.im).cos()) + self.c
+    }
+}
+impl Formula for Trig {
+    fn with_c(&self, c: Complex64) -> Trig {
+        Trig { c }
+    }
+    // Nothing is known about the shape of this set.
+    fn is_bounded(&self, _c: Complex64) -> bool {
+        false
+    }
+    // The cosines mix real and imaginary parts of z and c.
+    fn conjugate_symmetric(&self) -> bool {
+        false
+    }
+    fn name(&self) -> String {
+        "z^3 (cos(Re z Im c) + i cos(Re c Im z)) + c".to_owned()
+    }
+}
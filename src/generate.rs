@@ -0,0 +1,886 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::mem;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam;
+use num::complex::Complex64;
+use rand;
+use rand::Rng;
+
+use aggregators;
+use aggregators::Aggregator;
+use checkpoint::Checkpoint;
+use config;
+use config::{Aggregation, Config, ImageConfig, Orbits};
+use eta;
+use formulas::{
+    BurningShip, Celtic, Formula, FormulaConfig, Power, Quadratic, RealPower, Tricorn, Trig,
+};
+use header::{BandStatistics, Header};
+use image::HistogramFile;
+use location_generators;
+use location_generators::{LocationGenerator, Pilot};
+use math;
+use report::{EscapeReport, Outcome};
+use simd;
+
+enum Message {
+    Points(Vec<Complex64>),
+    Flush(crossbeam::Sender<io::Result<()>>),
+    Commit(usize, crossbeam::Sender<io::Result<()>>),
+}
+
+/// Runs the sampling. With `resume`, continues from the checkpoint file of an interrupted run
+/// instead of starting over.
+pub fn generate(config: Config, resume: bool) -> io::Result<()> {
+    match config.formula.clone() {
+        FormulaConfig::Quadratic => run(config, resume, Quadratic::default()),
+        FormulaConfig::Power { exponent } => run(config, resume, Power::new(exponent)),
+        FormulaConfig::RealPower { exponent } => run(config, resume, RealPower::new(exponent)),
+        FormulaConfig::BurningShip => run(config, resume, BurningShip::default()),
+        FormulaConfig::Tricorn => run(config, resume, Tricorn::default()),
+        FormulaConfig::Celtic => run(config, resume, Celtic::default()),
+        FormulaConfig::Trig => run(config, resume, Trig::default()),
+    }
+}
+
+fn run<F: Formula>(config: Config, resume: bool, formula: F) -> io::Result<()> {
+    println!(
+        "Estimated maximum RAM usage: {}mb",
+        config.estimated_memory_usage() / 1000000
+    );
+
+    let files = config
+        .images
+        .iter()
+        .map(|image| image.file_name.clone())
+        .collect::<Vec<_>>();
+
+    let checkpoint = if resume {
+        let checkpoint = Checkpoint::load(&config.checkpoint_file)?;
+        if checkpoint.files != files {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Checkpoint was written for different images",
+            ));
+        }
+        println!(
+            "Resuming after {} of {} samples",
+            checkpoint.samples_done, config.samples
+        );
+        Some(checkpoint)
+    } else {
+        None
+    };
+    let samples_done = checkpoint.as_ref().map_or(0, |checkpoint| checkpoint.samples_done);
+    let bands = match checkpoint {
+        Some(ref checkpoint) if checkpoint.bands.len() == config.images.len() => {
+            checkpoint.bands.clone()
+        }
+        _ => vec![BandStatistics::default(); config.images.len()],
+    };
+    let report = config.report_file.as_ref().map(|_| {
+        match checkpoint.as_ref().and_then(|checkpoint| checkpoint.report.clone()) {
+            Some(ref report) if report.escapes.len() == config.check_iterations + 1 => {
+                report.clone()
+            }
+            _ => EscapeReport::new(config.check_iterations),
+        }
+    });
+    // Workers add the statistics of every finished section, so at a checkpoint they cover
+    // exactly the samples done.
+    let bands = Arc::new(Mutex::new(bands));
+    let report = Arc::new(Mutex::new(report));
+
+    let seed = match (&checkpoint, config.seed) {
+        (Some(checkpoint), Some(seed)) if checkpoint.seed != seed => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Checkpoint was written with seed {}, but seed {} is configured",
+                    checkpoint.seed, seed
+                ),
+            ))
+        }
+        (Some(checkpoint), _) => checkpoint.seed,
+        (None, Some(seed)) => seed,
+        (None, None) => rand::random(),
+    };
+    println!("Using seed {}", seed);
+
+    // With symmetry, half of the samples in the upper half plane cover the whole plane.
+    let mirror = use_symmetry(&config, &formula);
+    let (scan_min, samples) = if mirror {
+        (Complex64::new(config.scan_min.re, 0.0), config.samples.div_ceil(2))
+    } else {
+        (config.scan_min, config.samples)
+    };
+    // Every mirrored sample stands for two samples of the whole plane. An odd sample count
+    // draws one half sample more, which is not counted, so a finished run records `samples`.
+    let total = config.samples as u64;
+    let recorded = |drawn: usize| {
+        if mirror {
+            (2 * drawn as u64).min(total)
+        } else {
+            drawn as u64
+        }
+    };
+
+    let location_generator = location_generators::UniformRandomLocationGenerator::new(
+        scan_min,
+        config.scan_max,
+        samples,
+        config.sample_section,
+        seed,
+    ).starting_at(samples_done);
+    let eta = eta::ETA::resume(
+        samples,
+        samples_done,
+        config.eta_section,
+        config.eta_time,
+    );
+
+    // The interior tests assume orbits starting at 0.
+    let skip_bounded = config.initial_z == Complex64::new(0.0, 0.0);
+
+    let pilot = match config.sampler {
+        config::Sampler::Metropolis { pilot_samples, .. } => Some(Arc::new(run_pilot(
+            &formula,
+            &config,
+            scan_min,
+            mirror,
+            skip_bounded,
+            seed,
+            pilot_samples,
+        )?)),
+        config::Sampler::Uniform => None,
+    };
+
+    // Workers hold a read lock while working on a section, so holding the write lock guarantees
+    // that all claimed sections are finished and sent to the aggregators.
+    let section_lock = Arc::new(RwLock::new(()));
+
+    let mut outputs = vec![];
+    let mut handles = Vec::<thread::JoinHandle<()>>::new();
+    for image in &config.images {
+        let header = Header::for_image(
+            image,
+            recorded(samples_done),
+            &formula.name(),
+            config.counter,
+        );
+        let journal = journal_file(&image.file_name);
+
+        let output = match config.aggregation {
+            Aggregation::Channel => {
+                let mut aggregator = if resume {
+                    let mut aggregator = aggregators::FileAggregator::open(
+                        &image.file_name,
+                        header,
+                        config.file_buffer_size,
+                        config.pixel_buffer_cutoff_size,
+                    )?;
+                    if aggregator.rollback(&journal, samples_done)? {
+                        println!("Rolled back {} to the checkpoint", image.file_name);
+                    }
+                    aggregator
+                } else {
+                    aggregators::FileAggregator::create(
+                        &image.file_name,
+                        header,
+                        config.file_buffer_size,
+                        config.pixel_buffer_cutoff_size,
+                    )?
+                };
+                if config.checkpoint_interval > 0 {
+                    aggregator.enable_journal(&journal, samples_done)?;
+                }
+                // let aggregator = aggregators::MemoryAggregator::new(
+                //     &image.file_name,
+                //     header,
+                //     config.file_buffer_size,
+                // );
+
+                let (sender, receiver) =
+                    crossbeam::channel::bounded::<Message>(config.channel_buffer);
+                handles.push(spawn_aggregator(
+                    receiver,
+                    aggregators::Mirror::new(aggregator, mirror),
+                ));
+                Output::Channel(sender)
+            }
+            Aggregation::Shared => {
+                let mut aggregator = if resume {
+                    let mut aggregator = aggregators::SharedAggregator::open(
+                        &image.file_name,
+                        header,
+                        config.file_buffer_size,
+                    )?;
+                    if aggregator.rollback(&journal, samples_done)? {
+                        println!("Rolled back {} to the checkpoint", image.file_name);
+                    }
+                    aggregator
+                } else {
+                    aggregators::SharedAggregator::create(
+                        &image.file_name,
+                        header,
+                        config.file_buffer_size,
+                    )?
+                };
+                if config.checkpoint_interval > 0 {
+                    aggregator.enable_journal(&journal, samples_done)?;
+                }
+
+                Output::Shared(aggregators::Mirror::new(aggregator, mirror))
+            }
+        };
+        outputs.push(output);
+    }
+
+    println!("Finished setting up aggregators");
+
+    let mut workers = vec![];
+    for thread_id in 0..config.threads {
+        let mut sampler = Sampler::new(
+            location_generator.clone(),
+            &config,
+            scan_min,
+            mirror,
+            pilot.clone(),
+        );
+        let mut eta = eta.clone();
+
+        let mut outputs = outputs.clone();
+        let config = config.clone();
+        let section_lock = section_lock.clone();
+        let bands = bands.clone();
+        let report = report.clone();
+        let formula = formula.clone();
+
+        workers.push(
+            thread::Builder::new()
+                .name(format!("Calculator {}", thread_id))
+                .spawn(move || {
+                    println!("Starting thread {}", thread_id);
+
+                    let mut result_caches =
+                        vec![Vec::with_capacity(config.thread_buffer); config.images.len()];
+                    let mut lengths = vec![0; config.images.len()];
+                    let mut statistics = vec![BandStatistics::default(); config.images.len()];
+                    let mut outcomes = config
+                        .report_file
+                        .as_ref()
+                        .map(|_| EscapeReport::new(config.check_iterations));
+
+                    let mut section_guard = section_lock.read().unwrap();
+                    while let Some(sample) =
+                        sampler.sample(&formula, &config, skip_bounded, &mut result_caches)
+                    {
+                        eta.count();
+                        if let Some(ref mut outcomes) = outcomes {
+                            outcomes.add(sample.recorded, sample.copies);
+                        }
+
+                        for (((statistics, image), cache), &length) in statistics
+                            .iter_mut()
+                            .zip(&config.images)
+                            .zip(&result_caches)
+                            .zip(&lengths)
+                        {
+                            count(statistics, image, &sample, &cache[length..], mirror);
+                        }
+
+                        let section_finished = sampler.section_finished();
+                        for (output, result_cache) in outputs.iter_mut().zip(result_caches.iter_mut()) {
+                            let length = result_cache.len();
+                            if length > config.thread_buffer || (section_finished && length > 0) {
+                                output.record(result_cache, config.thread_buffer);
+                            }
+                        }
+                        for (length, cache) in lengths.iter_mut().zip(&result_caches) {
+                            *length = cache.len();
+                        }
+
+                        if section_finished {
+                            for (band, statistics) in
+                                bands.lock().unwrap().iter_mut().zip(statistics.iter_mut())
+                            {
+                                band.add(statistics);
+                                *statistics = BandStatistics::default();
+                            }
+                            if let (Some(report), Some(outcomes)) =
+                                (report.lock().unwrap().as_mut(), outcomes.as_mut())
+                            {
+                                report.merge(outcomes);
+                                outcomes.clear();
+                            }
+                            drop(section_guard);
+                            section_guard = section_lock.read().unwrap();
+                        }
+                    }
+
+                    println!("Thread {} done", thread_id);
+                }).expect("Unable to start thread"),
+        );
+    }
+
+    let checkpoint_interval = Duration::from_secs(config.checkpoint_interval);
+    let mut last_checkpoint = Instant::now();
+    while workers.iter().any(|worker| !worker.is_finished()) {
+        thread::sleep(Duration::from_millis(100));
+
+        if config.checkpoint_interval > 0 && last_checkpoint.elapsed() >= checkpoint_interval {
+            let _sections = section_lock.write().unwrap();
+
+            let samples_done = location_generator.samples_drawn();
+            let bands = bands.lock().unwrap().clone();
+            let report = report.lock().unwrap().clone();
+
+            request_all(&mut outputs, Message::Flush, |aggregator| aggregator.flush())?;
+            write_progress(&config, recorded(samples_done), &bands)?;
+            Checkpoint {
+                samples: config.samples,
+                samples_done,
+                files: files.clone(),
+                seed,
+                bands,
+                report,
+            }.save(&config.checkpoint_file)?;
+            request_all(
+                &mut outputs,
+                |reply| Message::Commit(samples_done, reply),
+                |aggregator| aggregator.commit(samples_done),
+            )?;
+
+            println!("Saved checkpoint after {} samples", samples_done);
+
+            last_checkpoint = Instant::now();
+        }
+    }
+    for worker in workers {
+        worker.join().unwrap();
+    }
+
+    drop(outputs);
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let samples_done = location_generator.samples_drawn();
+    let bands = bands.lock().unwrap().clone();
+    write_progress(&config, recorded(samples_done), &bands)?;
+    println!("Took {} samples", recorded(samples_done));
+
+    let report = report.lock().unwrap().clone();
+    if let (Some(report), Some(path)) = (&report, &config.report_file) {
+        report.write(path, &config)?;
+        println!("Wrote the escape time report to {}", path);
+    }
+
+    if config.checkpoint_interval > 0 {
+        Checkpoint {
+            samples: config.samples,
+            samples_done,
+            files,
+            seed,
+            bands,
+            report,
+        }.save(&config.checkpoint_file)?;
+
+        for image in &config.images {
+            fs::remove_file(journal_file(&image.file_name))?;
+        }
+    }
+
+    for image in &config.images {
+        let statistics = HistogramFile::open(image)?.statistics(config.file_buffer_size)?;
+        if statistics.saturated > 0 {
+            println!(
+                "Warning: {} pixels of {} reached the counter ceiling of {}",
+                statistics.saturated,
+                image.file_name,
+                config.counter.ceiling()
+            );
+        }
+    }
+
+    Ok(())
+}
+/// Uniform samples whose bailout iterations are computed together, so batched kernels can
+/// iterate several of them at once.
+const BATCH: usize = 16 * simd::LANES;
+
+/// What a worker recorded for one drawn sample.
+struct Sample {
+    /// Outcome of the location whose orbit was recorded. The Metropolis sampler records the
+    /// current location instead of the sample drawn.
+    recorded: Outcome,
+    /// How often the orbit was recorded.
+    copies: u64,
+}
+
+/// The location generator of a worker thread, together with the buffers it needs.
+enum Sampler {
+    Uniform {
+        generator: location_generators::UniformRandomLocationGenerator,
+        /// Samples of the current section with their outcomes, in the order they were drawn.
+        batch: VecDeque<(Complex64, Outcome)>,
+        locations: Vec<Complex64>,
+        bailouts: Vec<Option<usize>>,
+    },
+    Metropolis {
+        generator: location_generators::MetropolisLocationGenerator,
+        proposal: Vec<Vec<Complex64>>,
+        current: Vec<Vec<Complex64>>,
+        /// Outcome of the current location.
+        current_outcome: Outcome,
+        /// Whether the aggregators mirror the points, which then count for the contribution
+        /// as well.
+        mirror: bool,
+    },
+}
+impl Sampler {
+    fn new(
+        uniform: location_generators::UniformRandomLocationGenerator,
+        config: &Config,
+        scan_min: Complex64,
+        mirror: bool,
+        pilot: Option<Arc<Pilot>>,
+    ) -> Sampler {
+        match config.sampler {
+            config::Sampler::Uniform => Sampler::Uniform {
+                generator: uniform,
+                batch: VecDeque::with_capacity(BATCH),
+                locations: Vec::with_capacity(BATCH),
+                bailouts: vec![None; BATCH],
+            },
+            config::Sampler::Metropolis {
+                mutation_radius,
+                large_step_probability,
+                ..
+            } => Sampler::Metropolis {
+                generator: location_generators::MetropolisLocationGenerator::new(
+                    uniform,
+                    scan_min,
+                    config.scan_max,
+                    mutation_radius,
+                    large_step_probability,
+                    pilot.expect("The Metropolis sampler needs a pilot"),
+                ),
+                proposal: vec![vec![]; config.images.len()],
+                current: vec![vec![]; config.images.len()],
+                current_outcome: Outcome::Skipped,
+                mirror,
+            },
+        }
+    }
+
+    /// Whether all samples of the current section are taken.
+    fn section_finished(&self) -> bool {
+        match self {
+            Sampler::Uniform {
+                generator, batch, ..
+            } => batch.is_empty() && generator.section_finished(),
+            Sampler::Metropolis { generator, .. } => generator.uniform().section_finished(),
+        }
+    }
+
+    /// Takes the next sample and adds the orbit points to record for it to `caches`. Returns
+    /// which orbit was recorded, or `None` once all samples are taken.
+    fn sample<F: Formula>(
+        &mut self,
+        formula: &F,
+        config: &Config,
+        skip_bounded: bool,
+        caches: &mut [Vec<Complex64>],
+    ) -> Option<Sample> {
+        match self {
+            Sampler::Uniform {
+                generator,
+                batch,
+                locations,
+                bailouts,
+            } => {
+                if batch.is_empty() {
+                    // Batches end with the section, so no sample of a finished section is
+                    // left over when a checkpoint is taken.
+                    while batch.len() < BATCH {
+                        let c = match generator.next_location() {
+                            Some(c) => c,
+                            None => break,
+                        };
+                        if skip_bounded && formula.is_bounded(c) {
+                            batch.push_back((c, Outcome::Skipped));
+                        } else {
+                            batch.push_back((c, Outcome::Bounded));
+                            locations.push(c);
+                        }
+                        if generator.section_finished() {
+                            break;
+                        }
+                    }
+
+                    formula.bailout_iterations(
+                        locations,
+                        config.initial_z,
+                        config.bailout_min,
+                        config.bailout_max,
+                        config.check_iterations,
+                        bailouts,
+                    );
+                    let iterated = batch
+                        .iter_mut()
+                        .filter(|&&mut (_, outcome)| outcome != Outcome::Skipped);
+                    for (&mut (_, ref mut outcome), bailout) in iterated.zip(bailouts.iter()) {
+                        if let Some(bailout) = *bailout {
+                            *outcome = Outcome::Escaped(bailout);
+                        }
+                    }
+                    locations.clear();
+                }
+
+                let (c, outcome) = batch.pop_front()?;
+                record(formula, c, config, outcome, caches);
+                Some(Sample {
+                    recorded: outcome,
+                    copies: 1,
+                })
+            }
+            Sampler::Metropolis {
+                generator,
+                proposal,
+                current,
+                current_outcome,
+                mirror,
+            } => {
+                let c = generator.next_location()?;
+
+                for points in proposal.iter_mut() {
+                    points.clear();
+                }
+                let outcome = trace(formula, c, config, skip_bounded, proposal);
+
+                if generator.accept(contribution(config, proposal, *mirror)) {
+                    mem::swap(proposal, current);
+                    *current_outcome = outcome;
+                }
+
+                let copies = generator.copies();
+                for _ in 0..copies {
+                    for (cache, points) in caches.iter_mut().zip(current.iter()) {
+                        cache.extend_from_slice(points);
+                    }
+                }
+                Some(Sample {
+                    recorded: *current_outcome,
+                    copies: copies as u64,
+                })
+            }
+        }
+    }
+}
+
+/// Number of orbit points in `points`, one list per image, that land inside their image. With
+/// `mirror`, the aggregators also record the conjugates, which count as well.
+fn contribution(config: &Config, points: &[Vec<Complex64>], mirror: bool) -> usize {
+    config
+        .images
+        .iter()
+        .zip(points)
+        .map(|(image, points)| {
+            points
+                .iter()
+                .map(|&z| {
+                    let mut count = 0;
+                    if math::complex_between(image.min, z, image.max) {
+                        count += 1;
+                    }
+                    if mirror && math::complex_between(image.min, z.conj(), image.max) {
+                        count += 1;
+                    }
+                    count
+                }).sum::<usize>()
+        }).sum()
+}
+
+/// Pilot samples traced by one thread at a time, each block from its own random stream.
+const PILOT_BLOCK: usize = 10_000;
+
+/// Traces `samples` uniform samples for the Metropolis sampler. The blocks draw from streams
+/// counting down from the last one, which no section reaches, so the pilot only depends on the
+/// seed and not on the number of threads.
+fn run_pilot<F: Formula>(
+    formula: &F,
+    config: &Config,
+    scan_min: Complex64,
+    mirror: bool,
+    skip_bounded: bool,
+    seed: u64,
+    samples: usize,
+) -> io::Result<Pilot> {
+    println!("Tracing {} pilot samples", samples);
+
+    let blocks = samples.div_ceil(PILOT_BLOCK);
+    let next = Arc::new(Mutex::new(0));
+    let mut workers = vec![];
+    for _ in 0..config.threads.min(blocks) {
+        let (formula, config, next) = (formula.clone(), config.clone(), next.clone());
+        workers.push(thread::spawn(move || {
+            let mut caches = vec![vec![]; config.images.len()];
+            let mut traced = vec![];
+            loop {
+                let block = {
+                    let mut next = next.lock().unwrap();
+                    if *next == blocks {
+                        break;
+                    }
+                    *next += 1;
+                    *next - 1
+                };
+
+                let mut rng = location_generators::derived_rng(seed, u64::MAX - block as u64);
+                let mut contributions = vec![];
+                for _ in block * PILOT_BLOCK..((block + 1) * PILOT_BLOCK).min(samples) {
+                    let c = Complex64::new(
+                        rng.gen_range(scan_min.re, config.scan_max.re),
+                        rng.gen_range(scan_min.im, config.scan_max.im),
+                    );
+                    for cache in &mut caches {
+                        cache.clear();
+                    }
+                    trace(&formula, c, &config, skip_bounded, &mut caches);
+
+                    let contribution = contribution(&config, &caches, mirror);
+                    if contribution > 0 {
+                        contributions.push((c, contribution));
+                    }
+                }
+                traced.push((block, contributions));
+            }
+            traced
+        }));
+    }
+
+    let mut traced = vec![];
+    for worker in workers {
+        traced.extend(worker.join().unwrap());
+    }
+    traced.sort_by_key(|&(block, _)| block);
+    let pilot = Pilot::new(
+        samples as u64,
+        traced.into_iter().flat_map(|(_, contributions)| contributions),
+    );
+
+    if pilot.mean() == 0.0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "No pilot sample of the Metropolis sampler reached the images; increase \
+             sampler.pilot_samples",
+        ));
+    }
+    println!("Mean contribution of the pilot samples: {}", pilot.mean());
+
+    Ok(pilot)
+}
+
+/// Iterates `c` and adds its orbit to the caches of all images whose band it falls into.
+fn trace<F: Formula>(
+    formula: &F,
+    c: Complex64,
+    config: &Config,
+    skip_bounded: bool,
+    caches: &mut [Vec<Complex64>],
+) -> Outcome {
+    // Points passing the interior test are known not to escape. They only have to be iterated
+    // for images of bounded orbits.
+    let outcome = if skip_bounded && formula.is_bounded(c) {
+        Outcome::Skipped
+    } else {
+        match math::calculate_bailout_iteration(
+            &mut formula.with_c(c),
+            config.initial_z,
+            config.bailout_min,
+            config.bailout_max,
+            config.check_iterations,
+        ) {
+            Some(bailout) => Outcome::Escaped(bailout),
+            None => Outcome::Bounded,
+        }
+    };
+
+    record(formula, c, config, outcome, caches);
+    outcome
+}
+
+/// Adds the orbit of `c` to the caches of all images whose band its outcome falls into.
+fn record<F: Formula>(
+    formula: &F,
+    c: Complex64,
+    config: &Config,
+    outcome: Outcome,
+    caches: &mut [Vec<Complex64>],
+) {
+    for (image, cache) in config.images.iter().zip(caches.iter_mut()) {
+        if in_band(image, outcome) {
+            math::calculate_iteration_values(
+                &mut formula.with_c(c),
+                config.initial_z,
+                config.bailout_min,
+                config.bailout_max,
+                image.min_iterations,
+                image.max_iterations,
+                cache,
+            );
+        }
+    }
+}
+
+/// Whether the orbit of a sample with this outcome is recorded in `image`.
+fn in_band(image: &ImageConfig, outcome: Outcome) -> bool {
+    match (image.orbits, outcome) {
+        (Orbits::Escaping, Outcome::Escaped(bailout)) => {
+            image.min_iterations <= bailout && bailout < image.max_iterations
+        }
+        (Orbits::Bounded, Outcome::Skipped) | (Orbits::Bounded, Outcome::Bounded) => true,
+        _ => false,
+    }
+}
+
+/// Whether only the upper half plane has to be sampled. Explains why not if symmetry was asked
+/// for but does not hold.
+fn use_symmetry<F: Formula>(config: &Config, formula: &F) -> bool {
+    if !config.symmetry {
+        return false;
+    }
+
+    let reason = if !formula.conjugate_symmetric() {
+        Some(format!("{} is not symmetric under conjugation", formula.name()))
+    } else if config.initial_z.im != 0.0 {
+        Some("the initial z is not real".to_owned())
+    } else if config.scan_min.im != -config.scan_max.im {
+        Some("the scan area is not symmetric to the real axis".to_owned())
+    } else if config.bailout_min.im != -config.bailout_max.im {
+        Some("the bailout area is not symmetric to the real axis".to_owned())
+    } else {
+        None
+    };
+
+    match reason {
+        Some(reason) => {
+            println!("Symmetry disabled: {}", reason);
+            false
+        }
+        None => {
+            println!("Sampling the upper half plane and mirroring all orbits");
+            true
+        }
+    }
+}
+
+/// Records the number of samples in the histograms and the band statistics in the headers of
+/// all files.
+fn write_progress(config: &Config, samples: u64, bands: &[BandStatistics]) -> io::Result<()> {
+    for (image, band) in config.images.iter().zip(bands) {
+        Header::write_progress(&image.file_name, samples, band)?;
+    }
+    Ok(())
+}
+
+/// Adds a sample and the points it deposited into the band of `image` to its statistics.
+/// Samples count as often as their orbit was recorded, even if it deposited no points.
+fn count(
+    statistics: &mut BandStatistics,
+    image: &ImageConfig,
+    sample: &Sample,
+    points: &[Complex64],
+    mirror: bool,
+) {
+    let copies = if mirror { 2 } else { 1 };
+    if in_band(image, sample.recorded) {
+        statistics.samples += copies * sample.copies;
+    }
+
+    let outside = |z: Complex64| !math::complex_between(image.min, z, image.max);
+    statistics.points += copies * points.len() as u64;
+    statistics.outside += points.iter().filter(|&&z| outside(z)).count() as u64;
+    if mirror {
+        statistics.outside += points.iter().filter(|&&z| outside(z.conj())).count() as u64;
+    }
+}
+
+fn journal_file(file_name: &str) -> String {
+    format!("{}.journal", file_name)
+}
+/// Where workers put the orbit points of an image.
+#[derive(Clone)]
+enum Output {
+    Channel(crossbeam::Sender<Message>),
+    Shared(aggregators::Mirror<aggregators::SharedAggregator>),
+}
+impl Output {
+    /// Hands over all points in `cache`, leaving it empty.
+    fn record(&mut self, cache: &mut Vec<Complex64>, capacity: usize) {
+        match self {
+            Output::Channel(sender) => send_with_warning(
+                sender,
+                Message::Points(mem::replace(cache, Vec::with_capacity(capacity))),
+            ),
+            Output::Shared(aggregator) => for c in cache.drain(..) {
+                aggregator.aggregate(c);
+            },
+        }
+    }
+}
+fn spawn_aggregator<A>(receiver: crossbeam::Receiver<Message>, mut aggregator: A) -> thread::JoinHandle<()>
+where
+    A: Aggregator + Send + 'static,
+{
+    thread::Builder::new()
+        .name("Aggregator".to_owned())
+        .spawn(move || {
+            for message in receiver {
+                match message {
+                    Message::Points(result) => for c in result {
+                        aggregator.aggregate(c);
+                    },
+                    Message::Flush(reply) => reply.send(aggregator.flush()),
+                    Message::Commit(samples_done, reply) => {
+                        reply.send(aggregator.commit(samples_done))
+                    }
+                }
+            }
+        }).expect("Unable to start thread")
+}
+/// Sends a message to every aggregator thread and waits until all of them handled it. Shared
+/// aggregators are handled directly.
+fn request_all<M, S>(outputs: &mut [Output], message: M, shared: S) -> io::Result<()>
+where
+    M: Fn(crossbeam::Sender<io::Result<()>>) -> Message,
+    S: Fn(&mut aggregators::Mirror<aggregators::SharedAggregator>) -> io::Result<()>,
+{
+    let (reply_sender, reply_receiver) = crossbeam::channel::unbounded();
+    let mut pending = 0;
+    for output in outputs.iter_mut() {
+        match output {
+            Output::Channel(sender) => {
+                sender.send(message(reply_sender.clone()));
+                pending += 1;
+            }
+            Output::Shared(aggregator) => shared(aggregator)?,
+        }
+    }
+    for _ in 0..pending {
+        reply_receiver.recv().unwrap()?;
+    }
+    Ok(())
+}
+fn send_with_warning(sender: &crossbeam::Sender<Message>, value: Message) {
+    if sender.is_full() {
+        println!("Bottleneck while sending");
+    }
+    sender.send(value);
+}
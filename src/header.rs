@@ -0,0 +1,295 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use num::complex::Complex64;
+
+use config::{Counter, ImageConfig, Orbits};
+
+pub const MAGIC: [u8; 4] = *b"MBH\0";
+pub const VERSION: u32 = 4;
+
+/// Size of the header in bytes. Pixel data starts right after it.
+pub const SIZE: u64 = 256;
+const FORMULA_SIZE: usize = 64;
+
+/// Describes the histogram stored in a .mbh file.
+///
+/// All values are stored in little endian, followed by `width * height` little endian pixel
+/// counts of the width given by `counter` in row-major order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Header {
+    pub width: u64,
+    pub height: u64,
+
+    pub min: Complex64,
+    pub max: Complex64,
+
+    pub min_iterations: u64,
+    pub max_iterations: u64,
+
+    pub samples: u64,
+    pub formula: String,
+
+    /// Added in version 2; older files always contain escaping orbits.
+    pub orbits: Orbits,
+    /// Added in version 3; older files always use `u32` counters.
+    pub counter: Counter,
+    /// Added in version 4; zero for older files.
+    pub band: BandStatistics,
+}
+
+/// What the samples of a run deposited into one band. Mirrored samples and points count twice.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct BandStatistics {
+    /// Samples whose orbits fall into the band, counted as often as they were recorded.
+    pub samples: u64,
+    /// All orbit points deposited, including those outside of the image.
+    pub points: u64,
+    /// Orbit points that missed the window of the image.
+    pub outside: u64,
+}
+impl BandStatistics {
+    pub fn add(&mut self, other: &BandStatistics) {
+        self.samples += other.samples;
+        self.points += other.points;
+        self.outside += other.outside;
+    }
+}
+
+impl Header {
+    pub fn for_image(
+        image: &ImageConfig,
+        samples: u64,
+        formula: &str,
+        counter: Counter,
+    ) -> Header {
+        Header {
+            width: image.width,
+            height: image.height,
+            min: image.min,
+            max: image.max,
+            min_iterations: image.min_iterations as u64,
+            max_iterations: image.max_iterations as u64,
+            samples,
+            formula: formula.to_owned(),
+            orbits: image.orbits,
+            counter,
+            band: BandStatistics::default(),
+        }
+    }
+
+    pub fn pixels(&self) -> u64 {
+        self.width * self.height
+    }
+
+    /// Count of a pixel in a `width`x`height` rendering of the histogram that corresponds to a
+    /// density of one hit per sample per unit area of the complex plane.
+    pub fn counts_per_density(&self, width: usize, height: usize) -> f64 {
+        let area = (self.max.re - self.min.re) * (self.max.im - self.min.im);
+        self.samples as f64 * area / (width * height) as f64
+    }
+
+    /// Whether both headers describe the same histogram, ignoring the sample count.
+    pub fn is_compatible(&self, other: &Header) -> bool {
+        self.difference(other).is_none()
+    }
+
+    /// Describes the first property in which two histograms differ, ignoring the sample count
+    /// and band statistics.
+    pub fn difference(&self, other: &Header) -> Option<String> {
+        if (self.width, self.height) != (other.width, other.height) {
+            Some(format!(
+                "dimensions {}x{} and {}x{} differ",
+                self.width, self.height, other.width, other.height
+            ))
+        } else if (self.min, self.max) != (other.min, other.max) {
+            Some(format!(
+                "bounds [{}, {}] and [{}, {}] differ",
+                self.min, self.max, other.min, other.max
+            ))
+        } else if (self.min_iterations, self.max_iterations)
+            != (other.min_iterations, other.max_iterations)
+        {
+            Some(format!(
+                "iteration bands {}..{} and {}..{} differ",
+                self.min_iterations, self.max_iterations, other.min_iterations, other.max_iterations
+            ))
+        } else if self.orbits != other.orbits {
+            Some(format!(
+                "recorded orbits {:?} and {:?} differ",
+                self.orbits, other.orbits
+            ))
+        } else if self.counter != other.counter {
+            Some(format!(
+                "counters {:?} and {:?} differ",
+                self.counter, other.counter
+            ))
+        } else if self.formula != other.formula {
+            Some(format!(
+                "formulas {} and {} differ",
+                self.formula, other.formula
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// Reads the header at the start of the file. Returns `None` for legacy files without header.
+    pub fn read(file: &mut File) -> io::Result<Option<Header>> {
+        file.seek(SeekFrom::Start(0))?;
+
+        let mut bytes = [0u8; SIZE as usize];
+        let mut read = 0;
+        while read < bytes.len() {
+            match file.read(&mut bytes[read..])? {
+                0 => return Ok(None),
+                n => read += n,
+            }
+        }
+        if bytes[0..4] != MAGIC {
+            return Ok(None);
+        }
+
+        let mut reader = Reader {
+            bytes: &bytes,
+            position: 4,
+        };
+
+        let version = reader.u32();
+        if version == 0 || version > VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unsupported .mbh version {}", version),
+            ));
+        }
+
+        let width = reader.u64();
+        let height = reader.u64();
+        let min = reader.complex();
+        let max = reader.complex();
+        let min_iterations = reader.u64();
+        let max_iterations = reader.u64();
+        let samples = reader.u64();
+        let formula_length = (reader.u32() as usize).min(FORMULA_SIZE);
+        let formula = String::from_utf8_lossy(&reader.bytes(FORMULA_SIZE)[..formula_length])
+            .into_owned();
+        let orbits = match (version, reader.u32()) {
+            (1, _) | (_, 0) => Orbits::Escaping,
+            _ => Orbits::Bounded,
+        };
+        let counter = match (version, reader.u32()) {
+            (1, _) | (2, _) | (_, 4) => Counter::U32,
+            (_, 8) => Counter::U64,
+            (_, bytes) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unsupported counter width of {} bytes", bytes),
+                ))
+            }
+        };
+        let band = if version >= 4 {
+            BandStatistics {
+                samples: reader.u64(),
+                points: reader.u64(),
+                outside: reader.u64(),
+            }
+        } else {
+            BandStatistics::default()
+        };
+
+        Ok(Some(Header {
+            width,
+            height,
+            min,
+            max,
+            min_iterations,
+            max_iterations,
+            samples,
+            formula,
+            orbits,
+            counter,
+            band,
+        }))
+    }
+
+    /// Replaces the sample count and band statistics in the header of an existing file.
+    pub fn write_progress(path: &str, samples: u64, band: &BandStatistics) -> io::Result<()> {
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+        match Header::read(&mut file)? {
+            Some(mut header) => {
+                header.samples = samples;
+                header.band = *band;
+                header.write(&mut file)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} has no header", path),
+            )),
+        }
+    }
+
+    pub fn write(&self, file: &mut File) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity(SIZE as usize);
+
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&self.width.to_le_bytes());
+        bytes.extend_from_slice(&self.height.to_le_bytes());
+        for value in &[self.min.re, self.min.im, self.max.re, self.max.im] {
+            bytes.extend_from_slice(&value.to_bits().to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.min_iterations.to_le_bytes());
+        bytes.extend_from_slice(&self.max_iterations.to_le_bytes());
+        bytes.extend_from_slice(&self.samples.to_le_bytes());
+
+        let formula = self.formula.as_bytes();
+        let formula = &formula[..formula.len().min(FORMULA_SIZE)];
+        bytes.extend_from_slice(&(formula.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(formula);
+        bytes.resize(4 + 4 + 8 * 9 + 4 + FORMULA_SIZE, 0);
+
+        let orbits: u32 = match self.orbits {
+            Orbits::Escaping => 0,
+            Orbits::Bounded => 1,
+        };
+        bytes.extend_from_slice(&orbits.to_le_bytes());
+        bytes.extend_from_slice(&(self.counter.bytes() as u32).to_le_bytes());
+        for value in &[self.band.samples, self.band.points, self.band.outside] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+
+        bytes.resize(SIZE as usize, 0);
+
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&bytes)
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, length: usize) -> &'a [u8] {
+        let bytes = &self.bytes[self.position..self.position + length];
+        self.position += length;
+        bytes
+    }
+
+    fn u32(&mut self) -> u32 {
+        let mut value = [0u8; 4];
+        value.copy_from_slice(self.bytes(4));
+        u32::from_le_bytes(value)
+    }
+    fn u64(&mut self) -> u64 {
+        let mut value = [0u8; 8];
+        value.copy_from_slice(self.bytes(8));
+        u64::from_le_bytes(value)
+    }
+    fn complex(&mut self) -> Complex64 {
+        let re = f64::from_bits(self.u64());
+        let im = f64::from_bits(self.u64());
+        Complex64::new(re, im)
+    }
+}
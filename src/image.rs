@@ -1,40 +1,309 @@
 use std::collections::BTreeMap;
-use std::fs::File;
+use std::fs;
+use std::fs::{File, OpenOptions};
 use std::io;
 
 use file_image;
 use num;
 
+use config::{Counter, ImageConfig};
 use file;
+use format::{Depth, Format, ImageStream};
+use header;
+use header::Header;
+use tone_map::{Distribution, Mapping};
 use vec;
 
+/// Summary of a histogram file, gathered chunk by chunk.
+pub struct Statistics {
+    pub highest: u64,
+    pub sum: u64,
+    pub nonzero: u64,
+    /// Pixels whose count reached the largest value of the counter.
+    pub saturated: u64,
+    pub distribution: Distribution,
+}
+impl Statistics {
+    pub fn read(
+        file: &mut File,
+        offset: u64,
+        counter: Counter,
+        pixels: u64,
+        chunk_size: usize,
+    ) -> io::Result<Statistics> {
+        let mut statistics = Statistics {
+            highest: 0,
+            sum: 0,
+            nonzero: 0,
+            saturated: 0,
+            distribution: Distribution::new(),
+        };
+
+        let mut buffer = vec::filled_with(0, chunk_size);
+        let mut location = 0;
+        while location < pixels {
+            let length = (pixels - location).min(chunk_size as u64) as usize;
+            file::read_counts(file, offset, counter, location, &mut buffer[..length])?;
+
+            statistics.distribution.add(&buffer[..length]);
+            for value in &buffer[..length] {
+                statistics.highest = statistics.highest.max(*value);
+                statistics.sum = statistics.sum.saturating_add(*value);
+                if *value > 0 {
+                    statistics.nonzero += 1;
+                }
+                if *value == counter.ceiling() {
+                    statistics.saturated += 1;
+                }
+            }
+
+            location += length as u64;
+        }
+
+        Ok(statistics)
+    }
+}
+
+/// A histogram file opened for streaming its counts.
+pub struct HistogramFile {
+    file: File,
+    header: Option<Header>,
+    offset: u64,
+    counter: Counter,
+
+    width: usize,
+    height: usize,
+}
+impl HistogramFile {
+    /// Opens the histogram of an image, falling back to its configured size for legacy files.
+    pub fn open(image: &ImageConfig) -> io::Result<HistogramFile> {
+        let mut file = OpenOptions::new().read(true).open(&image.file_name)?;
+
+        let header = Header::read(&mut file)?;
+        let (offset, counter, width, height) = match header {
+            Some(ref header) => (header::SIZE, header.counter, header.width, header.height),
+            None => (0, Counter::U32, image.width, image.height),
+        };
+        Ok(HistogramFile {
+            file,
+            header,
+            offset,
+            counter,
+            width: width as usize,
+            height: height as usize,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Converts the densities of the tone map of `image` to counts of a `width`x`height`
+    /// rendering of this histogram. `None` if the tone map uses no densities.
+    pub fn counts_per_density(
+        &self,
+        image: &ImageConfig,
+        width: usize,
+        height: usize,
+    ) -> io::Result<Option<f64>> {
+        if !image.tone_map.uses_density() {
+            return Ok(None);
+        }
+
+        match self.header {
+            Some(ref header) if header.samples > 0 => {
+                Ok(Some(header.counts_per_density(width, height)))
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} has no sample count to normalize the tone map with",
+                    image.file_name
+                ),
+            )),
+        }
+    }
+
+    /// Reads the counts starting at pixel `location`.
+    pub fn read(&mut self, location: u64, buffer: &mut [u64]) -> io::Result<()> {
+        file::read_counts(&mut self.file, self.offset, self.counter, location, buffer)
+    }
+
+    pub fn statistics(&mut self, chunk_size: usize) -> io::Result<Statistics> {
+        Statistics::read(
+            &mut self.file,
+            self.offset,
+            self.counter,
+            (self.width * self.height) as u64,
+            chunk_size,
+        )
+    }
+}
+
 pub struct ImageData {
-    data: Vec<u32>,
+    data: Vec<u64>,
+    header: Option<Header>,
 
     width: usize,
     height: usize,
 }
 impl ImageData {
+    /// Reads a histogram, taking its dimensions from the file header.
+    pub fn read(file: &mut File) -> io::Result<ImageData> {
+        match Header::read(file)? {
+            Some(header) => ImageData::read_with_header(file, header),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Missing .mbh header; dimensions have to be supplied for legacy files",
+            )),
+        }
+    }
+
+    /// Reads a histogram, falling back to the given dimensions for legacy files without header.
+    pub fn read_or_legacy(file: &mut File, width: usize, height: usize) -> io::Result<ImageData> {
+        match Header::read(file)? {
+            Some(header) => ImageData::read_with_header(file, header),
+            None => ImageData::read_fully(file, width, height),
+        }
+    }
+
+    /// Reads a legacy histogram without header.
     pub fn read_fully(file: &mut File, width: usize, height: usize) -> io::Result<ImageData> {
         let mut data = vec::filled_with(0, width * height);
-        file::read_u32(file, 0, &mut data)?;
+        file::read_counts(file, 0, Counter::U32, 0, &mut data)?;
 
         Ok(ImageData {
             data,
+            header: None,
             width,
             height,
         })
     }
 
+    pub fn from_data(data: Vec<u64>, width: usize, height: usize) -> ImageData {
+        ImageData {
+            data,
+            header: None,
+            width,
+            height,
+        }
+    }
+
+    fn read_with_header(file: &mut File, header: Header) -> io::Result<ImageData> {
+        let mut data = vec::filled_with(0, header.pixels() as usize);
+        file::read_counts(file, header::SIZE, header.counter, 0, &mut data)?;
+
+        Ok(ImageData {
+            data,
+            width: header.width as usize,
+            height: header.height as usize,
+            header: Some(header),
+        })
+    }
+
+    pub fn header(&self) -> Option<&Header> {
+        self.header.as_ref()
+    }
+    pub fn width(&self) -> usize {
+        self.width
+    }
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Adds the counts of another histogram of the same region and iteration band.
+    pub fn merge(&mut self, other: &ImageData) -> io::Result<()> {
+        let ceiling = match (&mut self.header, &other.header) {
+            (Some(header), Some(other)) => {
+                if let Some(difference) = header.difference(other) {
+                    return Err(incompatible(&difference));
+                }
+                header.samples += other.samples;
+                header.band.add(&other.band);
+                header.counter.ceiling()
+            }
+            _ => return Err(incompatible("legacy files without header can not be merged")),
+        };
+
+        for (value, other) in self.data.iter_mut().zip(&other.data) {
+            *value = value.saturating_add(*other).min(ceiling);
+        }
+
+        Ok(())
+    }
+
+    /// Sums the histogram files in `inputs` into `output`, reading `chunk_size` pixels of every
+    /// input at a time.
+    pub fn merge_files(inputs: &[&str], output: &str, chunk_size: usize) -> io::Result<Header> {
+        let mut files = vec![];
+        let mut header: Option<Header> = None;
+        for input in inputs {
+            let mut file = File::open(input)?;
+            let input_header = Header::read(&mut file)?.ok_or_else(|| {
+                incompatible(&format!("{} is a legacy file without header", input))
+            })?;
+
+            match header {
+                Some(ref mut header) => {
+                    if let Some(difference) = header.difference(&input_header) {
+                        return Err(incompatible(&format!("{}: {}", input, difference)));
+                    }
+                    header.samples += input_header.samples;
+                    header.band.add(&input_header.band);
+                }
+                None => header = Some(input_header),
+            }
+
+            files.push(file);
+        }
+        let header = header.ok_or_else(|| incompatible("nothing to merge"))?;
+
+        // The output may be one of the inputs, so it is only replaced once everything is read.
+        let temporary = format!("{}.tmp", output);
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&temporary)?;
+        header.write(&mut file)?;
+
+        let counter = header.counter;
+        let mut sum = vec::filled_with(0u64, chunk_size);
+        let mut buffer = vec::filled_with(0u64, chunk_size);
+        let mut location = 0;
+        while location < header.pixels() {
+            let length = (header.pixels() - location).min(chunk_size as u64) as usize;
+
+            file::read_counts(&mut files[0], header::SIZE, counter, location, &mut sum[..length])?;
+            for file in &mut files[1..] {
+                file::read_counts(file, header::SIZE, counter, location, &mut buffer[..length])?;
+                for (value, other) in sum.iter_mut().zip(&buffer[..length]) {
+                    *value = value.saturating_add(*other).min(counter.ceiling());
+                }
+            }
+            file::write_counts(&mut file, header::SIZE, counter, location, &sum[..length])?;
+
+            location += length as u64;
+        }
+        file.sync_all()?;
+        fs::rename(&temporary, output)?;
+
+        Ok(header)
+    }
+
+    /// Adds the counts of `image2` to `image1` without checking the headers.
     pub fn join(mut image1: ImageData, image2: ImageData) -> ImageData {
-        for (i, mut value) in image1.data.iter_mut().enumerate() {
-            *value = image2.data[i];
+        for (value, other) in image1.data.iter_mut().zip(&image2.data) {
+            *value = value.saturating_add(*other);
         }
 
         image1
     }
 
-    pub fn count_heights(&self) -> BTreeMap<u32, u64> {
+    pub fn count_heights(&self) -> BTreeMap<u64, u64> {
         let mut values = BTreeMap::new();
 
         for height in &self.data {
@@ -48,14 +317,21 @@ impl ImageData {
         values
     }
 
-    pub fn highest(&self) -> u32 {
+    pub fn highest(&self) -> u64 {
         *self.data.iter().max().unwrap()
     }
-    pub fn sum(&self) -> u32 {
-        self.data.iter().sum::<u32>()
+    pub fn distribution(&self) -> Distribution {
+        let mut distribution = Distribution::new();
+        distribution.add(&self.data);
+        distribution
+    }
+    pub fn sum(&self) -> u64 {
+        self.data
+            .iter()
+            .fold(0, |sum: u64, value| sum.saturating_add(*value))
     }
 
-    pub fn map_to_image1(&self, map: &Fn(u32, u32) -> u8, color_type: file_image::ColorType) -> Image {
+    pub fn map_to_image1(&self, map: &dyn Fn(u64, u64) -> u8, color_type: file_image::ColorType) -> Image {
         let highest = self.highest();
 
         let mut mapped = Vec::with_capacity(self.data.len());
@@ -64,8 +340,8 @@ impl ImageData {
         }
 
         Image {
-            data: mapped,
-            color_type: color_type,
+            samples: Samples::Eight(mapped),
+            color_type,
 
             width: self.width,
             height: self.height,
@@ -76,7 +352,7 @@ impl ImageData {
     }
 
 
-    pub fn map_to_image3(&self, map: &Fn(u32, u32) -> [u8; 3], color_type: file_image::ColorType) -> Image {
+    pub fn map_to_image3(&self, map: &dyn Fn(u64, u64) -> [u8; 3], color_type: file_image::ColorType) -> Image {
         let highest = self.highest();
 
         let mut mapped = Vec::with_capacity(self.data.len());
@@ -85,8 +361,8 @@ impl ImageData {
         }
 
         Image {
-            data: mapped,
-            color_type: color_type,
+            samples: Samples::Eight(mapped),
+            color_type,
 
             width: self.width,
             height: self.height,
@@ -94,41 +370,146 @@ impl ImageData {
     }
     // TODO map_to_image3/4 methods for converting single picture to color
 
+    pub fn map_to_gray(&self, mapping: &Mapping) -> Image {
+        self.map_to_image1(
+            &|i, _| to_u8(mapping.apply(i)),
+            file_image::Gray(8),
+        )
+    }
+    pub fn map_to_gray_as(&self, mapping: &Mapping, depth: Depth) -> Image {
+        Image::from_brightness(
+            self.data.iter().map(|i| mapping.apply(*i)),
+            false,
+            self.width,
+            self.height,
+            depth,
+        )
+    }
+    /// Tints the tone mapped brightness with an RGB color with components between 0 and 1.
+    pub fn map_to_color(&self, mapping: &Mapping, color: [f64; 3]) -> Image {
+        self.map_to_image3(
+            &|i, _| {
+                let brightness = mapping.apply(i);
+                [
+                    to_u8(brightness * color[0]),
+                    to_u8(brightness * color[1]),
+                    to_u8(brightness * color[2]),
+                ]
+            },
+            file_image::RGB(8),
+        )
+    }
 
 
-    pub fn map(&mut self, map: &Fn(u32) -> u32) -> &mut ImageData {
+
+    pub fn map(&mut self, map: &dyn Fn(u64) -> u64) -> &mut ImageData {
         for i in &mut self.data {
             *i = map(*i);
         }
 
         self
     }
+}
+
+fn to_u8(value: f64) -> u8 {
+    (num::clamp(value, 0.0, 1.0) * 255.0).round() as u8
+}
+fn to_u16(value: f64) -> u16 {
+    (num::clamp(value, 0.0, 1.0) * 65535.0).round() as u16
+}
+
+fn interleave<T: Copy>(a: &[T], b: &[T], c: &[T]) -> Vec<T> {
+    let mut data = Vec::with_capacity(a.len() * 3);
+    for i in 0..a.len() {
+        data.push(a[i]);
+        data.push(b[i]);
+        data.push(c[i]);
+    }
+    data
+}
 
+fn incompatible(message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("Can not merge histograms: {}", message),
+    )
+}
 
-    // pub fn map_linear_count(&self, minimum_height: u32) -> Image {
-    //     let heights = self.count_heights();
-    //     let sum: u64 = heights.values().skip(minimum_height as usize).sum();
+/// How a band contributes to an RGB composite.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Layer {
+    /// RGB components between 0 and 1.
+    pub color: [f64; 3],
+    #[serde(default = "default_weight")]
+    pub weight: f64,
+}
 
-    //     let mut current_sum = 0;
+fn default_weight() -> f64 {
+    1.0
+}
 
-    //     let height_colors = heights
-    //         .iter()
-    //         .map(|(height, count)| (*height, *count))
-    //         .map(|(height, count)| {
-    //             if height < minimum_height {
-    //                 (height, 0)
-    //             } else {
-    //                 current_sum += count;
-    //                 (height, (current_sum / (sum / 255)) as u8)
-    //             }
-    //         }).collect::<BTreeMap<_, _>>();
+/// Blends any number of bands into an RGB image, each tinted by the color of its layer.
+pub struct Composite {
+    data: Vec<f32>,
 
-    //     self.map_color1(&|i| *height_colors.get(&i).unwrap())
-    // }
+    width: usize,
+    height: usize,
+}
+impl Composite {
+    pub fn new(width: usize, height: usize) -> Composite {
+        Composite {
+            data: vec::filled_with(0.0, width * height * 3),
+            width,
+            height,
+        }
+    }
+
+    pub fn add(&mut self, image: &ImageData, mapping: &Mapping, layer: &Layer) -> io::Result<()> {
+        if (image.width, image.height) != (self.width, self.height) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Can not composite a {}x{} band into a {}x{} image",
+                    image.width, image.height, self.width, self.height
+                ),
+            ));
+        }
+
+        for (pixel, value) in self.data.chunks_mut(3).zip(&image.data) {
+            let brightness = mapping.apply(*value) * layer.weight;
+            for (channel, color) in pixel.iter_mut().zip(&layer.color) {
+                *channel += (brightness * color) as f32;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn to_image(&self) -> Image {
+        self.to_image_as(Depth::Eight)
+    }
+    pub fn to_image_as(&self, depth: Depth) -> Image {
+        Image::from_brightness(
+            self.data.iter().map(|value| f64::from(*value)),
+            true,
+            self.width,
+            self.height,
+            depth,
+        )
+    }
+}
+
+/// Pixels of an `Image`, row by row with interleaved channels.
+pub enum Samples {
+    Eight(Vec<u8>),
+    Sixteen(Vec<u16>),
+    /// Brightness as is, which is only clipped to 0..1 by the other depths.
+    Float(Vec<f32>),
 }
 
 pub struct Image {
-    data: Vec<u8>,
+    samples: Samples,
 
     color_type: file_image::ColorType,
 
@@ -142,29 +523,96 @@ impl Image {
         image3: &Image,
         color_type: file_image::ColorType,
     ) -> Image {
-        let mut data = Vec::with_capacity(image1.data.len() * 3);
-
-        for i in 0..image1.data.len() {
-            data.push(image1.data[i]);
-            data.push(image2.data[i]);
-            data.push(image3.data[i]);
-        }
+        let samples = match (&image1.samples, &image2.samples, &image3.samples) {
+            (Samples::Eight(a), Samples::Eight(b), Samples::Eight(c)) => {
+                Samples::Eight(interleave(a, b, c))
+            }
+            (Samples::Sixteen(a), Samples::Sixteen(b), Samples::Sixteen(c)) => {
+                Samples::Sixteen(interleave(a, b, c))
+            }
+            (Samples::Float(a), Samples::Float(b), Samples::Float(c)) => {
+                Samples::Float(interleave(a, b, c))
+            }
+            _ => panic!("Can not join images of different depths"),
+        };
 
         Image {
-            data,
+            samples,
             color_type,
             width: image1.width,
             height: image1.height,
         }
     }
 
+    /// Quantizes brightness values to the given depth.
+    pub fn from_brightness<I: Iterator<Item = f64>>(
+        values: I,
+        rgb: bool,
+        width: usize,
+        height: usize,
+        depth: Depth,
+    ) -> Image {
+        let (samples, bits) = match depth {
+            Depth::Eight => (Samples::Eight(values.map(to_u8).collect()), 8),
+            Depth::Sixteen => (Samples::Sixteen(values.map(to_u16).collect()), 16),
+            Depth::Float => (
+                Samples::Float(values.map(|value| value as f32).collect()),
+                32,
+            ),
+        };
+
+        Image {
+            samples,
+            color_type: if rgb {
+                file_image::RGB(bits)
+            } else {
+                file_image::Gray(bits)
+            },
+            width,
+            height,
+        }
+    }
+
+    pub fn samples(&self) -> &Samples {
+        &self.samples
+    }
+    pub fn depth(&self) -> Depth {
+        match self.samples {
+            Samples::Eight(_) => Depth::Eight,
+            Samples::Sixteen(_) => Depth::Sixteen,
+            Samples::Float(_) => Depth::Float,
+        }
+    }
+    pub fn channels(&self) -> usize {
+        match self.color_type {
+            file_image::RGB(_) => 3,
+            _ => 1,
+        }
+    }
+    pub fn width(&self) -> usize {
+        self.width
+    }
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Saves 8 bit images in any format the image crate supports. Deeper images are saved as
+    /// PNG or TIFF with 16 bit, or as PFM with floats.
     pub fn save(&self, file: &str) -> io::Result<()> {
-        file_image::save_buffer(
-            file,
-            &self.data[..],
-            self.width as u32,
-            self.height as u32,
-            self.color_type,
-        )
+        if let Samples::Eight(ref data) = self.samples {
+            return file_image::save_buffer(
+                file,
+                &data[..],
+                self.width as u32,
+                self.height as u32,
+                self.color_type,
+            );
+        }
+
+        let format = Format::for_path(file, self.depth())?;
+        let mut stream =
+            ImageStream::create(file, format, self.width, self.height, self.channels() == 3)?;
+        stream.write(self)?;
+        stream.finish()
     }
 }
@@ -0,0 +1,104 @@
+use std::fs::OpenOptions;
+use std::io;
+
+use config::{Config, Counter};
+use header;
+use header::Header;
+use image::Statistics;
+
+pub fn info(config: &Config) -> io::Result<()> {
+    println!(
+        "Sampling {} points in [{}, {}] in sections of {}",
+        config.samples, config.scan_min, config.scan_max, config.sample_section
+    );
+    match config.seed {
+        Some(seed) => println!("Sampler: {:?}; seed {}", config.sampler, seed),
+        None => println!("Sampler: {:?}; random seed", config.sampler),
+    }
+    println!(
+        "Using {} threads; estimated maximum RAM usage: {}mb",
+        config.threads,
+        config.estimated_memory_usage() / 1000000
+    );
+
+    for image in &config.images {
+        println!(
+            "Iterations {}..{} of {:?} orbits: {}x{} in [{}, {}] -> {}",
+            image.min_iterations,
+            image.max_iterations,
+            image.orbits,
+            image.width,
+            image.height,
+            image.min,
+            image.max,
+            image.file_name
+        );
+
+        match OpenOptions::new().read(true).open(&image.file_name) {
+            Ok(mut file) => {
+                let (offset, counter, pixels) = match Header::read(&mut file)? {
+                    Some(header) => {
+                        println!(
+                            "    {}x{} in [{}, {}]; iterations {}..{} of {:?} orbits; {} samples of {}; {:?} counters",
+                            header.width,
+                            header.height,
+                            header.min,
+                            header.max,
+                            header.min_iterations,
+                            header.max_iterations,
+                            header.orbits,
+                            header.samples,
+                            header.formula,
+                            header.counter
+                        );
+                        if header.band.samples > 0 {
+                            println!(
+                                "    {} samples in the band deposited {} points, {} of them outside of the image; {:.1} points per sample",
+                                header.band.samples,
+                                header.band.points,
+                                header.band.outside,
+                                header.band.points as f64 / header.band.samples as f64
+                            );
+                        }
+                        (header::SIZE, header.counter, header.pixels())
+                    }
+                    None => {
+                        println!("    legacy file without header");
+                        (0, Counter::U32, image.width * image.height)
+                    }
+                };
+
+                let statistics = Statistics::read(
+                    &mut file,
+                    offset,
+                    counter,
+                    pixels,
+                    config.file_buffer_size,
+                )?;
+                println!(
+                    "    highest {}; sum {}; {} of {} pixels hit",
+                    statistics.highest, statistics.sum, statistics.nonzero, pixels
+                );
+                if statistics.saturated > 0 {
+                    println!(
+                        "    {} pixels reached the counter ceiling of {}",
+                        statistics.saturated,
+                        counter.ceiling()
+                    );
+                }
+                println!(
+                    "    percentiles of hit pixels: 50% {}; 99% {}; 99.9% {}",
+                    statistics.distribution.quantile(0.5),
+                    statistics.distribution.quantile(0.99),
+                    statistics.distribution.quantile(0.999)
+                );
+            }
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => {
+                println!("    not generated yet");
+            }
+            Err(error) => return Err(error),
+        }
+    }
+
+    Ok(())
+}
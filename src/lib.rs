@@ -0,0 +1,33 @@
+extern crate crossbeam;
+extern crate deflate;
+extern crate image as file_image;
+extern crate num;
+extern crate png;
+extern crate rand;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate toml;
+
+pub mod aggregators;
+pub mod checkpoint;
+pub mod config;
+pub mod eta;
+pub mod file;
+pub mod format;
+pub mod formulas;
+pub mod generate;
+pub mod header;
+pub mod image;
+pub mod info;
+pub mod location_generators;
+pub mod math;
+pub mod png_stream;
+pub mod render;
+pub mod report;
+pub mod resample;
+pub mod simd;
+pub mod tiles;
+pub mod tone_map;
+pub mod vec;
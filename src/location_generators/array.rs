@@ -4,8 +4,14 @@ use num::complex::Complex64;
 use rand;
 use rand::Rng;
 
+use location_generators;
+
+/// Samples `per_point` points around each of the given locations. The offsets around a location
+/// are drawn from a stream derived from `seed` and the index of the location.
 pub struct ArrayLocationGenerator {
     locations: Arc<Mutex<Vec<Complex64>>>,
+    seed: u64,
+    rng: rand::prng::XorShiftRng,
 
     current: Option<Complex64>,
     count: u64,
@@ -13,9 +19,16 @@ pub struct ArrayLocationGenerator {
     delta: f64,
 }
 impl ArrayLocationGenerator {
-    pub fn new(locations: Vec<Complex64>, per_point: u64, delta: f64) -> ArrayLocationGenerator {
+    pub fn new(
+        locations: Vec<Complex64>,
+        per_point: u64,
+        delta: f64,
+        seed: u64,
+    ) -> ArrayLocationGenerator {
         ArrayLocationGenerator {
             locations: Arc::new(Mutex::new(locations)),
+            seed,
+            rng: location_generators::derived_rng(seed, 0),
             current: None,
             count: 0,
             per_point,
@@ -28,19 +41,17 @@ impl ::location_generators::LocationGenerator<Complex64> for ArrayLocationGenera
     fn next_location(&mut self) -> Option<Complex64> {
         if self.count >= self.per_point || self.current.is_none() {
             self.count = 0;
-            self.current = self.locations.lock().unwrap().pop();
-
-            if !self.current.is_some() {
-                return None;
-            }
+            let mut locations = self.locations.lock().unwrap();
+            self.current = Some(locations.pop()?);
+            self.rng = location_generators::derived_rng(self.seed, locations.len() as u64);
         }
 
         self.count += 1;
 
         let current = self.current.unwrap();
         Some(Complex64::new(
-            current.re + rand::thread_rng().gen_range(-self.delta, self.delta),
-            current.im + rand::thread_rng().gen_range(-self.delta, self.delta),
+            current.re + self.rng.gen_range(-self.delta, self.delta),
+            current.im + self.rng.gen_range(-self.delta, self.delta),
         ))
     }
 }
@@ -49,6 +60,8 @@ impl Clone for ArrayLocationGenerator {
     fn clone(&self) -> ArrayLocationGenerator {
         ArrayLocationGenerator {
             locations: self.locations.clone(),
+            seed: self.seed,
+            rng: self.rng.clone(),
             current: None,
             count: 0,
             per_point: self.per_point,
@@ -0,0 +1,195 @@
+use std::sync::Arc;
+
+use num::complex::Complex64;
+use rand::distributions::Normal;
+use rand::Rng;
+
+use location_generators::{LocationGenerator, UniformRandomLocationGenerator};
+use math;
+
+/// Metropolis–Hastings sampling of `c`, preferring locations whose orbits contribute to the
+/// images.
+///
+/// Every step takes one sample of the wrapped uniform generator. With `large_step_probability`
+/// it is used directly as proposal, otherwise the current location is moved by a normal
+/// distributed offset. After the orbit of the proposal was traced, `accept` has to be called with
+/// the number of its points inside the images.
+///
+/// The current location is then distributed proportionally to its contribution `f(c)`, so its
+/// orbit has to be recorded `mean(f) / f(c)` times per step for the histograms to match uniform
+/// sampling. The mean is taken from a `Pilot` of uniform samples, which is fixed before the run,
+/// so it neither depends on the progress of a chain nor on how the samples are split into
+/// sections.
+pub struct MetropolisLocationGenerator {
+    uniform: UniformRandomLocationGenerator,
+    min: Complex64,
+    max: Complex64,
+
+    mutation: Normal,
+    large_step_probability: f64,
+    pilot: Arc<Pilot>,
+
+    proposal: Complex64,
+    current: Option<(Complex64, usize)>,
+}
+impl MetropolisLocationGenerator {
+    pub fn new(
+        uniform: UniformRandomLocationGenerator,
+        min: Complex64,
+        max: Complex64,
+        mutation_radius: f64,
+        large_step_probability: f64,
+        pilot: Arc<Pilot>,
+    ) -> MetropolisLocationGenerator {
+        MetropolisLocationGenerator {
+            uniform,
+            min,
+            max,
+
+            mutation: Normal::new(0.0, mutation_radius),
+            large_step_probability,
+            pilot,
+
+            proposal: Complex64::new(0.0, 0.0),
+            current: None,
+        }
+    }
+
+    pub fn uniform(&self) -> &UniformRandomLocationGenerator {
+        &self.uniform
+    }
+    pub fn uniform_mut(&mut self) -> &mut UniformRandomLocationGenerator {
+        &mut self.uniform
+    }
+
+    /// Decides whether the last proposal becomes the current location, given the number of its
+    /// orbit points inside the images.
+    pub fn accept(&mut self, contribution: usize) -> bool {
+        // Mutations can leave the scanned area, where the target density is zero.
+        let accept = match self.current {
+            _ if contribution == 0 || !math::complex_between(self.min, self.proposal, self.max) => {
+                false
+            }
+            Some((_, current)) if contribution < current => {
+                self.uniform.rng_mut().gen::<f64>() * (current as f64) < contribution as f64
+            }
+            _ => true,
+        };
+
+        if accept {
+            self.current = Some((self.proposal, contribution));
+        }
+        accept
+    }
+
+    /// How often the orbit of the current location has to be recorded for the last step.
+    ///
+    /// The weight is rounded stochastically, which keeps the expected counts exact.
+    pub fn copies(&mut self) -> usize {
+        let contribution = match self.current {
+            Some((_, contribution)) => contribution,
+            None => return 0,
+        };
+
+        let weight = self.pilot.mean() / contribution as f64;
+        let copies = weight.floor();
+
+        if self.uniform.rng_mut().gen::<f64>() < weight - copies {
+            copies as usize + 1
+        } else {
+            copies as usize
+        }
+    }
+}
+
+impl LocationGenerator<Complex64> for MetropolisLocationGenerator {
+    fn next_location(&mut self) -> Option<Complex64> {
+        let restart = self.uniform.section_finished();
+        let uniform = self.uniform.next_location()?;
+
+        // Every section starts a new chain, so sections do not depend on each other. It starts
+        // at a pilot location drawn proportionally to its contribution, which is already the
+        // distribution the chain converges to.
+        if restart {
+            self.current = self.pilot.draw(self.uniform.rng_mut());
+        }
+
+        let large_step =
+            self.current.is_none() || self.uniform.rng_mut().gen::<f64>() < self.large_step_probability;
+
+        self.proposal = match self.current {
+            Some((current, _)) if !large_step => {
+                let mutation = self.mutation;
+                let rng = self.uniform.rng_mut();
+                current + Complex64::new(rng.sample(mutation), rng.sample(mutation))
+            }
+            _ => uniform,
+        };
+
+        Some(self.proposal)
+    }
+}
+
+impl Clone for MetropolisLocationGenerator {
+    fn clone(&self) -> MetropolisLocationGenerator {
+        MetropolisLocationGenerator {
+            uniform: self.uniform.clone(),
+            pilot: self.pilot.clone(),
+            proposal: Complex64::new(0.0, 0.0),
+            current: None,
+            ..*self
+        }
+    }
+}
+
+/// Contributions of uniform samples taken before the run. They give the mean contribution all
+/// chains are normalized by, and the locations new chains start at.
+pub struct Pilot {
+    samples: u64,
+    /// Locations with a contribution, together with it.
+    locations: Vec<(Complex64, usize)>,
+    /// Sum of the contributions of all locations up to and including the same index.
+    cumulative: Vec<u64>,
+}
+impl Pilot {
+    /// Takes the contributions of `samples` uniform samples. Locations without a contribution
+    /// may be left out.
+    pub fn new<I>(samples: u64, contributions: I) -> Pilot
+    where
+        I: IntoIterator<Item = (Complex64, usize)>,
+    {
+        let mut pilot = Pilot {
+            samples,
+            locations: vec![],
+            cumulative: vec![],
+        };
+
+        let mut sum = 0;
+        for (c, contribution) in contributions {
+            if contribution > 0 {
+                sum += contribution as u64;
+                pilot.locations.push((c, contribution));
+                pilot.cumulative.push(sum);
+            }
+        }
+
+        pilot
+    }
+
+    /// Estimated mean contribution of uniform samples.
+    pub fn mean(&self) -> f64 {
+        match self.cumulative.last() {
+            Some(&sum) => sum as f64 / self.samples as f64,
+            None => 0.0,
+        }
+    }
+
+    /// Draws one of the contributing locations with a probability proportional to its
+    /// contribution, or `None` if no pilot sample contributed.
+    fn draw<R: Rng>(&self, rng: &mut R) -> Option<(Complex64, usize)> {
+        let sum = *self.cumulative.last()?;
+        let target = rng.gen_range(0, sum);
+        let i = self.cumulative.partition_point(|&cumulative| cumulative <= target);
+        Some(self.locations[i])
+    }
+}
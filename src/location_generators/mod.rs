@@ -2,7 +2,33 @@ mod uniform_random;
 pub use self::uniform_random::UniformRandomLocationGenerator;
 mod array;
 pub use self::array::ArrayLocationGenerator;
+mod metropolis;
+pub use self::metropolis::{MetropolisLocationGenerator, Pilot};
+
+use rand::prng::XorShiftRng;
+use rand::SeedableRng;
 
 pub trait LocationGenerator<T> {
     fn next_location(&mut self) -> Option<T>;
 }
+
+/// Derives an independent random number generator for `stream` from a master seed.
+///
+/// Streams are keyed by what they sample (e.g. the section index) rather than by thread, so the
+/// results do not depend on how work is distributed between threads.
+pub fn derived_rng(seed: u64, stream: u64) -> XorShiftRng {
+    // SplitMix64, which turns consecutive inputs into uncorrelated outputs.
+    let mut state = seed ^ stream.wrapping_mul(0xD1B5_4A32_D192_ED03);
+    let mut next = || {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    };
+
+    let mut bytes = [0u8; 16];
+    bytes[..8].copy_from_slice(&next().to_le_bytes());
+    bytes[8..].copy_from_slice(&next().to_le_bytes());
+    XorShiftRng::from_seed(bytes)
+}
@@ -2,18 +2,28 @@ use std::sync::{atomic::AtomicUsize, atomic::Ordering, Arc};
 
 use num::complex::Complex64;
 use rand;
-use rand::{Rng, SeedableRng};
+use rand::Rng;
 
-// TODO taking a task should already increase total to prevent other thread from trying
+use location_generators;
+
+/// Samples uniformly in sections of `section_total` points. Every section draws from its own
+/// stream derived from `seed`, so the samples do not depend on which thread takes a section.
+/// The last section is shortened, so exactly `total` samples are drawn.
 pub struct UniformRandomLocationGenerator {
     min: Complex64,
     max: Complex64,
     total: usize,
+    /// Start of the next unclaimed section.
     current: Arc<AtomicUsize>,
+    /// Samples of all sections finished so far.
+    drawn: Arc<AtomicUsize>,
 
     section_total: usize,
+    /// Length of the current section, which is only shorter than `section_total` for the last.
+    section_length: usize,
     section_current: usize,
 
+    seed: u64,
     rng: rand::prng::XorShiftRng,
 }
 impl UniformRandomLocationGenerator {
@@ -23,42 +33,83 @@ impl UniformRandomLocationGenerator {
         max: Complex64,
         total: usize,
         section_total: usize,
+        seed: u64,
     ) -> UniformRandomLocationGenerator {
         UniformRandomLocationGenerator {
             min,
             max,
             total,
             current: Arc::new(AtomicUsize::new(0)),
+            drawn: Arc::new(AtomicUsize::new(0)),
 
             section_total,
+            section_length: 0,
             section_current: 0,
 
-            rng: rand::prng::XorShiftRng::from_rng(rand::thread_rng()).unwrap(),
+            seed,
+            rng: location_generators::derived_rng(seed, 0),
         }
     }
+
+    /// Continues a previous run that already took `current` samples.
+    pub fn starting_at(self, current: usize) -> UniformRandomLocationGenerator {
+        self.current.store(current, Ordering::Relaxed);
+        self.drawn.store(current, Ordering::Relaxed);
+        self
+    }
+
+    /// Number of samples in all sections finished so far, by any thread. Once all threads are
+    /// done, this is exactly `total`.
+    pub fn samples_drawn(&self) -> usize {
+        self.drawn.load(Ordering::Relaxed)
+    }
+    pub fn section_finished(&self) -> bool {
+        self.section_current == 0
+    }
+
+    pub fn rng_mut(&mut self) -> &mut rand::prng::XorShiftRng {
+        &mut self.rng
+    }
 }
 
 impl ::location_generators::LocationGenerator<Complex64> for UniformRandomLocationGenerator {
     fn next_location(&mut self) -> Option<Complex64> {
         if self.section_current == 0 {
-            let current = self.current.load(Ordering::Relaxed);
-
-            if current >= self.total {
-                return None;
-            }
+            let (total, section_total) = (self.total, self.section_total);
+            // Claims the section and clips it to the total in one step, so no thread can claim
+            // samples past the end.
+            let start = match self.current.fetch_update(
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+                |current| {
+                    if current < total {
+                        Some((current + section_total).min(total))
+                    } else {
+                        None
+                    }
+                },
+            ) {
+                Ok(start) => start,
+                Err(_) => return None,
+            };
 
+            let section = start / self.section_total;
             println!(
                 "Starting section {}/{}",
-                current / self.section_total + 1,
-                self.total / self.section_total
+                section + 1,
+                self.total.div_ceil(self.section_total)
             );
 
-            self.current
-                .fetch_add(self.section_total, Ordering::Relaxed);
-            self.section_current = self.section_total;
+            self.rng = location_generators::derived_rng(self.seed, section as u64);
+            self.section_length = (self.total - start).min(self.section_total);
+            self.section_current = self.section_length;
         }
 
         self.section_current -= 1;
+        if self.section_current == 0 {
+            self.drawn
+                .fetch_add(self.section_length, Ordering::Relaxed);
+        }
 
         Some(Complex64::new(
             self.rng.gen_range(self.min.re, self.max.re),
@@ -74,11 +125,14 @@ impl Clone for UniformRandomLocationGenerator {
             max: self.max,
             total: self.total,
             current: self.current.clone(),
+            drawn: self.drawn.clone(),
 
             section_total: self.section_total,
+            section_length: 0,
             section_current: 0,
 
-            rng: rand::prng::XorShiftRng::from_rng(rand::thread_rng()).unwrap(),
+            seed: self.seed,
+            rng: self.rng.clone(),
         }
     }
 }
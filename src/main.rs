@@ -1,440 +1,183 @@
-extern crate crossbeam;
-extern crate image as file_image;
-extern crate num;
-extern crate rand;
+extern crate clap;
+extern crate mandelbuddha;
 
-use std::fs::OpenOptions;
-use std::thread;
+use std::fs;
+use std::process;
 
-use num::complex::Complex64;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 
-pub mod aggregators;
-pub mod eta;
-pub mod file;
-pub mod image;
-pub mod location_generators;
-pub mod math;
-pub mod vec;
-use aggregators::Aggregator;
-use location_generators::LocationGenerator;
-
-#[derive(Clone, Copy)]
-struct CalculateNext {
-    c: Complex64,
-}
-impl CalculateNext {
-    fn get(&self, c: Complex64) -> CalculateNext {
-        CalculateNext { c }
-    }
-}
-impl math::CalculateNext for CalculateNext {
-    fn next(&mut self, z: Complex64) -> Complex64 {
-        z * z + self.c
-        // z * z * z * Complex64::new((z.re * self.c.im).cos(), (self.c.re * z.im).cos()) + self.c
-    }
-}
-
-#[derive(Clone)]
-struct Config<'a> {
-    function: CalculateNext,
-    initial_z: Complex64,
-
-    bailout_min: Complex64,
-    bailout_max: Complex64,
-
-    scan_min: Complex64,
-    scan_max: Complex64,
-    samples: usize,
-    sample_section: usize,
-
-    check_iterations: usize,
-    images: Vec<ImageConfig<'a>>,
-
-    eta_section: usize,
-    eta_time: u64,
-
-    threads: usize,
-    channel_buffer: usize,
-    thread_buffer: usize,
-
-    file_buffer_size: usize,
-    pixel_buffer_cutoff_size: usize,
-
-    image_file_name: &'a str,
-}
-#[derive(Clone)]
-struct ImageConfig<'a> {
-    min_iterations: usize,
-    max_iterations: usize,
-
-    width: u64,
-    height: u64,
-
-    min: Complex64,
-    max: Complex64,
-
-    file_name: &'a str,
-}
+use mandelbuddha::config::Config;
+use mandelbuddha::image::ImageData;
+use mandelbuddha::{generate, info, render, tiles};
 
 fn main() {
-    let config = Config {
-        function: CalculateNext {
-            c: Complex64::new(0.0, 0.0),
-        },
-        initial_z: Complex64::new(0.0, 0.0),
-
-        bailout_min: Complex64::new(-2.0, -2.0),
-        bailout_max: Complex64::new(2.0, 2.0),
-
-        scan_min: Complex64::new(-2.0, -2.0),
-        scan_max: Complex64::new(2.0, 2.0),
-        samples: 1.3e11 as usize,
-        sample_section: 1e6 as usize,
-
-        check_iterations: 10_000,
-        images: vec![
-            // ImageConfig {
-            //     min_iterations: 0,
-            //     max_iterations: 10,
-
-            //     width: 30_000,
-            //     height: 30_000,
-
-            //     min: Complex64::new(-2.0, -2.0),
-            //     max: Complex64::new(2.0, 2.0),
-
-            //     file_name: "image-0-10.mbh",
-            // },
-            ImageConfig {
-                min_iterations: 10,
-                max_iterations: 20,
-
-                width: 30_000,
-                height: 30_000,
-
-                min: Complex64::new(-2.0, -2.0),
-                max: Complex64::new(2.0, 2.0),
-
-                file_name: "image-10-20.mbh",
-            },
-            ImageConfig {
-                min_iterations: 20,
-                max_iterations: 50,
-
-                width: 30_000,
-                height: 30_000,
-
-                min: Complex64::new(-2.0, -2.0),
-                max: Complex64::new(2.0, 2.0),
-
-                file_name: "image-20-50.mbh",
-            },
-            ImageConfig {
-                min_iterations: 50,
-                max_iterations: 100,
-
-                width: 30_000,
-                height: 30_000,
-
-                min: Complex64::new(-2.0, -2.0),
-                max: Complex64::new(2.0, 2.0),
-
-                file_name: "image-50-100.mbh",
-            },
-            ImageConfig {
-                min_iterations: 100,
-                max_iterations: 200,
-
-                width: 30_000,
-                height: 30_000,
-
-                min: Complex64::new(-2.0, -2.0),
-                max: Complex64::new(2.0, 2.0),
-
-                file_name: "image-100-200.mbh",
-            },
-            ImageConfig {
-                min_iterations: 200,
-                max_iterations: 500,
-
-                width: 30_000,
-                height: 30_000,
-
-                min: Complex64::new(-2.0, -2.0),
-                max: Complex64::new(2.0, 2.0),
-
-                file_name: "image-200-500.mbh",
-            },
-            ImageConfig {
-                min_iterations: 500,
-                max_iterations: 1000,
-
-                width: 30_000,
-                height: 30_000,
-
-                min: Complex64::new(-2.0, -2.0),
-                max: Complex64::new(2.0, 2.0),
-
-                file_name: "image-500-1000.mbh",
-            },
-            ImageConfig {
-                min_iterations: 1000,
-                max_iterations: 2000,
-
-                width: 30_000,
-                height: 30_000,
-
-                min: Complex64::new(-2.0, -2.0),
-                max: Complex64::new(2.0, 2.0),
-
-                file_name: "image-1000-2000.mbh",
-            },
-            ImageConfig {
-                min_iterations: 2000,
-                max_iterations: 5000,
-
-                width: 30_000,
-                height: 30_000,
-
-                min: Complex64::new(-2.0, -2.0),
-                max: Complex64::new(2.0, 2.0),
-
-                file_name: "image-2000-5000.mbh",
-            },
-            ImageConfig {
-                min_iterations: 5000,
-                max_iterations: 10000,
-
-                width: 30_000,
-                height: 30_000,
-
-                min: Complex64::new(-2.0, -2.0),
-                max: Complex64::new(2.0, 2.0),
-
-                file_name: "image-5000-10000.mbh",
-            },
-        ],
-
-        eta_section: 10,
-        eta_time: 1000,
-
-        threads: 16,
-        channel_buffer: 4,
-        thread_buffer: 1e6 as usize,
-
-        file_buffer_size: 1e7 as usize,
-        pixel_buffer_cutoff_size: 3e6 as usize,
-
-        image_file_name: "image.png",
+    let overrides = [
+        Arg::with_name("config")
+            .help("Config file (.toml or .json); defaults to the bundled preset")
+            .index(1),
+        Arg::with_name("samples")
+            .long("samples")
+            .takes_value(true)
+            .help("Number of samples to take"),
+        Arg::with_name("threads")
+            .long("threads")
+            .takes_value(true)
+            .help("Number of worker threads"),
+        Arg::with_name("seed")
+            .long("seed")
+            .takes_value(true)
+            .help("Master seed for reproducible runs"),
+        Arg::with_name("bands")
+            .long("bands")
+            .takes_value(true)
+            .help("Comma separated iteration bands like 10-20,20-50 replacing the configured images"),
+        Arg::with_name("output-dir")
+            .long("output-dir")
+            .takes_value(true)
+            .help("Directory for histogram and image files"),
+    ];
+
+    let matches = App::new("mandelbuddha")
+        .about("Renders the Buddhabrot")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("generate")
+                .about("Samples orbits into .mbh histograms")
+                .args(&overrides)
+                .arg(
+                    Arg::with_name("resume")
+                        .long("resume")
+                        .help("Continues an interrupted run from its checkpoint"),
+                ),
+        ).subcommand(
+            SubCommand::with_name("render")
+                .about("Renders existing .mbh histograms to images")
+                .args(&overrides),
+        ).subcommand(
+            SubCommand::with_name("tiles")
+                .about("Exports .mbh histograms as DeepZoom tile pyramids")
+                .args(&overrides)
+                .arg(
+                    Arg::with_name("tile-size")
+                        .long("tile-size")
+                        .takes_value(true)
+                        .default_value("256")
+                        .help("Width and height of the tiles in pixels"),
+                ),
+        ).subcommand(
+            SubCommand::with_name("info")
+                .about("Shows the effective config and the state of its histograms")
+                .args(&overrides),
+        ).subcommand(
+            SubCommand::with_name("merge")
+                .about("Adds up .mbh histograms of the same region and iteration band")
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Merged .mbh file"),
+                ).arg(
+                    Arg::with_name("chunk-size")
+                        .long("chunk-size")
+                        .takes_value(true)
+                        .default_value("10000000")
+                        .help("Pixels read from every input at a time"),
+                ).arg(
+                    Arg::with_name("inputs")
+                        .required(true)
+                        .multiple(true)
+                        .min_values(2)
+                        .help("Histograms to merge"),
+                ),
+        ).get_matches();
+
+    let result = match matches.subcommand() {
+        ("generate", Some(matches)) => {
+            generate::generate(load_config(matches), matches.is_present("resume"))
+        }
+        ("render", Some(matches)) => render::render(&load_config(matches)),
+        ("tiles", Some(matches)) => {
+            let tile_size = parse_number("tile-size", matches.value_of("tile-size").unwrap());
+            if tile_size == 0 {
+                exit_with(&"Invalid value for --tile-size: 0");
+            }
+            tiles::export(&load_config(matches), tile_size)
+        }
+        ("info", Some(matches)) => info::info(&load_config(matches)),
+        ("merge", Some(matches)) => {
+            let chunk_size = parse_number("chunk-size", matches.value_of("chunk-size").unwrap());
+            if chunk_size == 0 {
+                exit_with(&"Invalid value for --chunk-size: 0");
+            }
+            ImageData::merge_files(
+                &matches.values_of("inputs").unwrap().collect::<Vec<_>>(),
+                matches.value_of("output").unwrap(),
+                chunk_size,
+            ).map(|header| println!("Merged {} samples", header.samples))
+        }
+        _ => unreachable!(),
     };
 
-    generate(config.clone());
-    image(config.clone());
-}
-
-fn generate(config: Config<'static>) {
-    println!(
-        "Estimated maximum RAM usage: {}mb",
-        ((config.threads + config.channel_buffer) * config.thread_buffer * 2 * 8 * config.images.len()
-            + (config
-                .images
-                .iter()
-                .map(|image| image.width as usize * image.height as usize)
-                .sum::<usize>()
-                / config.file_buffer_size
-                + 1)
-                * config.pixel_buffer_cutoff_size
-                * 4)
-            / 1000000
-    );
-
-    let location_generator = location_generators::UniformRandomLocationGenerator::new(
-        config.scan_min,
-        config.scan_max,
-        config.samples,
-        config.sample_section,
-    );
-    let eta = eta::ETA::new(config.samples, config.eta_section, config.eta_time);
-
-    let mut senders = vec![];
-    let mut receivers = vec![];
-    for _ in &config.images {
-        let (sender, receiver) =
-            crossbeam::channel::bounded::<Option<Vec<Complex64>>>(config.channel_buffer);
-        senders.push(sender);
-        receivers.push(receiver);
+    if let Err(error) = result {
+        eprintln!("{}", error);
+        process::exit(1);
     }
+}
 
-    for thread_id in 0..config.threads {
-        // TODO this really has to be cleaned up.
-
-        // TODO investigate large performance degredation in comparision to single image(Reference: 48e52238)
-        let mut location_generator = location_generator.clone();
-        let mut eta = eta.clone();
-
-        let senders = senders.clone();
-        let config = config.clone();
-
-        thread::Builder::new()
-            .name(format!("Calculator {}", thread_id))
-            .spawn(move || {
-                println!("Starting thread {}", thread_id);
-
-                let mut result_caches =
-                    vec![Some(Vec::with_capacity(config.thread_buffer)); config.images.len()];
-
-                while let Some(c) = location_generator.next_location() {
-                    eta.count();
-
-                    if math::is_inside_mandelbrot_bulb(c) {
-                        continue;
-                    }
-
-                    if let Some(bailout) = math::calculate_bailout_iteration(
-                        &mut config.function.get(c),
-                        config.initial_z,
-                        config.bailout_min,
-                        config.bailout_max,
-                        config.check_iterations,
-                    ) {
-                        for (i, image) in config.images.iter().enumerate() {
-                            if image.min_iterations <= bailout && bailout < image.max_iterations {
-                                math::calculate_iteration_values(
-                                    &mut config.function.get(c),
-                                    config.initial_z,
-                                    config.bailout_min,
-                                    config.bailout_max,
-                                    image.min_iterations,
-                                    image.max_iterations,
-                                    &mut result_caches[i].as_mut().unwrap(),
-                                );
-                            }
-                        }
-                    }
-
-                    for (i, _) in config.images.iter().enumerate() {
-                        if result_caches[i].as_ref().unwrap().len() > config.thread_buffer {
-                            send_with_warning(&senders[i], Some(result_caches[i].take().unwrap()));
-                            result_caches[i] = Some(Vec::with_capacity(config.thread_buffer));
-                        }
-                    }
-                }
-
-                for (i, _) in config.images.iter().enumerate() {
-                    send_with_warning(&senders[i], Some(result_caches[i].take().unwrap()));
-                    send_with_warning(&senders[i], None);
-                }
+fn load_config(matches: &ArgMatches) -> Config {
+    let mut config = match matches.value_of("config") {
+        Some(path) => Config::load(path).unwrap_or_else(|error| exit_with(&error)),
+        None => Config::default_preset(),
+    };
 
-                println!("Thread {} done", thread_id);
-            }).expect("Unable to start thread");
+    if let Some(samples) = matches.value_of("samples") {
+        config.samples = parse_number("samples", samples);
     }
-
-    let mut aggregators = vec![];
-
-    let mut handles = Vec::<thread::JoinHandle<()>>::new();
-    for (i, receiver) in receivers.drain(..).enumerate() {
-        let image = &config.images[i];
-
-
-        let aggregator = aggregators::FileAggregator::create(
-            image.file_name,
-            image.width,
-            image.height,
-            image.min,
-            image.max,
-            config.file_buffer_size,
-            config.pixel_buffer_cutoff_size,
-        ).expect("Error while setting up aggregator");
-        // let aggregator = aggregators::MemoryAggregator::new(
-        //     image.file_name,
-        //     image.width,
-        //     image.height,
-        //     image.min,
-        //     image.max,
-        //     config.file_buffer_size,
-        // );
-
-        aggregators.push((receiver, aggregator));
+    if let Some(threads) = matches.value_of("threads") {
+        config.threads = parse_number("threads", threads);
     }
-
-    println!("Finished setting up aggregators");
-
-    for (receiver, mut aggregator) in aggregators.drain(..) {
-        let config = config.clone();
-
-        handles.push(
-            thread::Builder::new()
-                .name("Aggregator".to_owned())
-                .spawn(move || {
-                    let mut received = 0;
-
-                    while received < config.threads {
-                        let result = receiver.recv().unwrap();
-                        if let Some(result) = result {
-                            for c in result {
-                                aggregator.aggregate(c);
-                            }
-                        } else {
-                            received += 1;
-                        }
-                    }
-                }).expect("Unable to start thread"),
+    if let Some(seed) = matches.value_of("seed") {
+        config.seed = Some(
+            seed.parse()
+                .unwrap_or_else(|_| exit_with(&format!("Invalid value for --seed: {}", seed))),
         );
     }
-
-    for handle in handles {
-        handle.join().unwrap();
+    if let Some(bands) = matches.value_of("bands") {
+        let bands = bands.split(',').map(parse_band).collect::<Vec<_>>();
+        config.set_bands(&bands);
     }
-}
-fn send_with_warning(
-    sender: &crossbeam::Sender<Option<Vec<Complex64>>>,
-    value: Option<Vec<Complex64>>,
-) {
-    if sender.is_full() {
-        println!("Bottleneck while sending");
+    if let Some(dir) = matches.value_of("output-dir") {
+        fs::create_dir_all(dir).unwrap_or_else(|error| {
+            exit_with(&format!("Can not create output directory {}: {}", dir, error))
+        });
+        config.set_output_dir(dir);
     }
-    sender.send(value);
-}
-
-fn image(config: Config) {
-    // TODO separate image size; downsampling
-    println!("Preparing color channels");
-
-    // for image in config.images {
-    //     println!("Loading image from {}", image.file_name);
-    //     let mut file = OpenOptions::new()
-    //         .read(true)
-    //         .open(image.file_name)
-    //         .expect("Could not open file");
-
-    //     use num;
-    //     use std::f64::consts::E;
 
-    //     image::ImageData::read_fully(
-    //             &mut file,
-    //             image.width as usize,
-    //             image.height as usize,
-    //         ).expect("Could not read file")
-    //         // .map(&|i: u32| ((i as f64).sqrt() * 10000.0) as u32)
-    //         // .map_to_grayscale_linear(1.0)
-    //         .map_to_image1(&|i, highest| num::clamp((1.0 - E.powf(-2.0 * (i as f64 / highest as f64))) * 255.0 * 2.0, 0.0, 255.0) as u8, file_image::Gray(8))
-    //         .save(&(image.file_name.to_owned() + ".png"))
-    //         .unwrap();
-    // }
-
-    
+    config.validate().unwrap_or_else(|error| exit_with(&error));
+    config
+}
 
+/// Parses a count, also accepting scientific notation like `1.3e11`.
+fn parse_number(name: &str, value: &str) -> usize {
+    value
+        .parse::<usize>()
+        .ok()
+        .or_else(|| value.parse::<f64>().ok().map(|value| value as usize))
+        .unwrap_or_else(|| exit_with(&format!("Invalid value for --{}: {}", name, value)))
+}
 
-    // TODO tiled writing?
+fn parse_band(band: &str) -> (usize, usize) {
+    let mut bounds = band.splitn(2, '-').map(|bound| bound.trim().parse::<usize>());
 
-    // println!("Joining images");
-    // let image = image::Image::join(
-    //     &images[2],
-    //     &images[1],
-    //     &images[0],
-    //     file_image::ColorType::RGB(8),
-    // );
+    match (bounds.next(), bounds.next()) {
+        (Some(Ok(min)), Some(Ok(max))) => (min, max),
+        _ => exit_with(&format!("Invalid iteration band: {}", band)),
+    }
+}
 
-    // println!("Saving image");
-    // image.save(config.image_file_name).unwrap();
+fn exit_with<T: std::fmt::Display>(message: &T) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
 }
@@ -8,7 +8,44 @@ pub trait CalculateNext {
     fn next(&mut self, z: Complex64) -> Complex64;
 }
 
+/// Squared distance below which an orbit counts as having returned to an earlier point.
+pub const CYCLE_TOLERANCE: f64 = 1e-24;
+
+/// Brent's cycle detection. The orbit is compared with a point saved at every power of two
+/// iterations, so a cycle of any period is found within a few periods after the orbit settled.
+struct CycleDetector {
+    saved: Complex64,
+    power: usize,
+    steps: usize,
+}
+impl CycleDetector {
+    fn new(initial: Complex64) -> CycleDetector {
+        CycleDetector {
+            saved: initial,
+            power: 1,
+            steps: 0,
+        }
+    }
+
+    /// Takes the next point of the orbit. Returns the period once the orbit came back to the
+    /// saved point.
+    fn period(&mut self, z: Complex64) -> Option<usize> {
+        self.steps += 1;
+        if (z - self.saved).norm_sqr() < CYCLE_TOLERANCE {
+            return Some(self.steps);
+        }
+        if self.steps == self.power {
+            self.saved = z;
+            self.power *= 2;
+            self.steps = 0;
+        }
+        None
+    }
+}
+
 // Note: Combining both calculate_* methods heavily decreases performance
+/// Returns the iteration in which the orbit leaves the bailout area, or `None` if it stays
+/// inside for `max_iterations` or runs into a cycle.
 pub fn calculate_bailout_iteration<CN: CalculateNext>(
     next: &mut CN,
     initial: Complex64,
@@ -18,15 +55,13 @@ pub fn calculate_bailout_iteration<CN: CalculateNext>(
 ) -> Option<usize> {
     let mut z = initial;
     let mut iterations = 0;
+    let mut cycles = CycleDetector::new(z);
 
     while complex_between(bailout_min, z, bailout_max) && iterations < max_iterations {
-        let new_z = next.next(z);
-        if new_z == z {
-            iterations = max_iterations;
-            break;
-        } else {
-            z = new_z;
-            iterations += 1;
+        z = next.next(z);
+        iterations += 1;
+        if cycles.period(z).is_some() {
+            return None;
         }
     }
     if complex_between(bailout_min, z, bailout_max) {
@@ -36,6 +71,10 @@ pub fn calculate_bailout_iteration<CN: CalculateNext>(
     }
 }
 
+/// Adds the points of the orbit after iteration `min_iterations` up to `max_iterations`.
+///
+/// Cycles are detected exactly like in `calculate_bailout_iteration`, so both agree on which
+/// orbits escape. Once the orbit runs into a cycle, the remaining points repeat it.
 pub fn calculate_iteration_values<CN: CalculateNext>(
     next: &mut CN,
     initial: Complex64,
@@ -47,21 +86,29 @@ pub fn calculate_iteration_values<CN: CalculateNext>(
 ) {
     let mut z = initial;
     let mut iterations = 0;
+    let mut cycles = CycleDetector::new(z);
 
     while complex_between(bailout_min, z, bailout_max) && iterations < max_iterations {
-        let new_z = next.next(z);
-        if new_z == z {
-            for _ in iterations..max_iterations {
-                //TODO apply min_iterations here
-                results.push(z);
+        z = next.next(z);
+        if iterations >= min_iterations {
+            results.push(z);
+        }
+        iterations += 1;
+
+        if let Some(period) = cycles.period(z) {
+            let remaining = max_iterations - iterations;
+            let mut cycle = Vec::with_capacity(period.min(remaining));
+            let mut w = z;
+            for _ in 0..period.min(remaining) {
+                w = next.next(w);
+                cycle.push(w);
             }
-            break;
-        } else {
-            z = new_z;
-            if iterations >= min_iterations {
-                results.push(z);
+
+            let skipped = min_iterations.saturating_sub(iterations);
+            for i in skipped..remaining {
+                results.push(cycle[i % period]);
             }
-            iterations += 1;
+            break;
         }
     }
 }
@@ -79,11 +126,38 @@ pub fn complex_to_image(
     )
 }
 
+/// Discs inside hyperbolic components of period 3 and higher, as center and radius. They
+/// were fitted into the curve on which the attracting cycle has a multiplier of 0.99, and
+/// shrunk a little further. Components off the real axis are mirrored.
+const BULBS: [(f64, f64, f64); 5] = [
+    // Period 3, attached to the main cardioid.
+    (-0.1249, 0.7439, 0.09),
+    // Period 4, attached to the main cardioid.
+    (0.2811, 0.531, 0.042),
+    // Period 4, attached to the period 2 bulb.
+    (-1.3091, 0.0, 0.056),
+    // Period 5, attached to the main cardioid.
+    (-0.5045, 0.563, 0.037),
+    (0.3793, 0.3359, 0.0225),
+];
+
+/// Whether `c` lies in the main cardioid, the period 2 bulb or one of the larger bulbs of
+/// higher period, so its orbit starting at 0 never escapes. Points on which the test fails may
+/// still be bounded.
 pub fn is_inside_mandelbrot_bulb(c: Complex64) -> bool {
     let x = c.re;
     let y = c.im;
 
-    let p = (x - 1.0 / 4.0) * (x - 1.0 / 4.0) + y * y;
+    let q = (x - 0.25) * (x - 0.25) + y * y;
+    if q * (q + (x - 0.25)) < 0.25 * y * y {
+        return true;
+    }
+    if (x + 1.0) * (x + 1.0) + y * y < 1.0 / 16.0 {
+        return true;
+    }
 
-    x < p - 2.0 * p * p + 1.0 / 4.0 && (x + 1.0) * (x + 1.0) + y * y < 1.0 / 16.0
+    let y = y.abs();
+    BULBS.iter().any(|&(center_x, center_y, radius)| {
+        (x - center_x) * (x - center_x) + (y - center_y) * (y - center_y) < radius * radius
+    })
 }
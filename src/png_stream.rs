@@ -0,0 +1,127 @@
+use std::fs::File;
+use std::io;
+use std::io::Write;
+
+use deflate;
+use deflate::write::ZlibEncoder;
+use png;
+use png::HasParameters;
+
+/// Size of the compressed data collected before it is written as one IDAT chunk.
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// Writes a PNG row by row, so images larger than memory can be encoded.
+pub struct PngStream {
+    encoder: ZlibEncoder<Chunks>,
+
+    bytes_per_pixel: usize,
+    row_length: usize,
+    rows_left: u64,
+}
+
+/// Splits the compressed stream into IDAT chunks.
+struct Chunks {
+    writer: png::Writer<File>,
+    buffer: Vec<u8>,
+}
+impl Write for Chunks {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(data);
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            self.writer.write_chunk(png::chunk::IDAT, &self.buffer)?;
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+}
+
+impl PngStream {
+    /// Creates an 8 or 16 bit gray or RGB PNG. 16 bit samples are written in big endian.
+    pub fn create(
+        path: &str,
+        width: usize,
+        height: usize,
+        rgb: bool,
+        sixteen_bit: bool,
+    ) -> io::Result<PngStream> {
+        let mut encoder = png::Encoder::new(File::create(path)?, width as u32, height as u32);
+        let mut bytes_per_pixel = if rgb {
+            encoder.set(png::ColorType::RGB);
+            3
+        } else {
+            encoder.set(png::ColorType::Grayscale);
+            1
+        };
+        if sixteen_bit {
+            encoder.set(png::BitDepth::Sixteen);
+            bytes_per_pixel *= 2;
+        } else {
+            encoder.set(png::BitDepth::Eight);
+        }
+
+        Ok(PngStream {
+            encoder: ZlibEncoder::new(
+                Chunks {
+                    writer: encoder.write_header()?,
+                    buffer: Vec::with_capacity(CHUNK_SIZE),
+                },
+                deflate::Compression::Fast,
+            ),
+
+            bytes_per_pixel,
+            row_length: width * bytes_per_pixel,
+            rows_left: height as u64,
+        })
+    }
+
+    /// Appends whole rows of pixels.
+    pub fn write_rows(&mut self, data: &[u8]) -> io::Result<()> {
+        if !data.len().is_multiple_of(self.row_length)
+            || (data.len() / self.row_length) as u64 > self.rows_left
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Image data does not match the PNG size",
+            ));
+        }
+
+        let mut filtered = vec![0; self.row_length];
+        for row in data.chunks(self.row_length) {
+            // Sub filter: every byte is stored relative to the one of the pixel to the left.
+            for (i, byte) in filtered.iter_mut().enumerate() {
+                *byte = if i < self.bytes_per_pixel {
+                    row[i]
+                } else {
+                    row[i].wrapping_sub(row[i - self.bytes_per_pixel])
+                };
+            }
+
+            self.encoder.write_all(&[1])?;
+            self.encoder.write_all(&filtered)?;
+            self.rows_left -= 1;
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        if self.rows_left > 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} rows of the PNG are missing", self.rows_left),
+            ));
+        }
+
+        let mut chunks = self.encoder.finish()?;
+        chunks.flush()?;
+        // Dropping the writer adds the IEND chunk.
+        Ok(())
+    }
+}
@@ -0,0 +1,196 @@
+use std::io;
+use std::path::Path;
+
+use config::{Config, ImageConfig};
+use format::ImageStream;
+use image;
+use image::HistogramFile;
+use resample;
+use resample::Output;
+use tone_map::Mapping;
+use vec;
+
+pub fn render(config: &Config) -> io::Result<()> {
+    println!("Preparing color channels");
+
+    match config.output {
+        Some(ref output) => render_resampled(config, output),
+        None => render_streamed(config),
+    }
+}
+
+fn render_resampled(config: &Config, output: &Output) -> io::Result<()> {
+    let mut composite: Option<image::Composite> = None;
+
+    for image in &config.images {
+        println!("Loading image from {}", image.file_name);
+        let mut histogram = HistogramFile::open(image)?;
+
+        println!(
+            "Resampling from {}x{} to {}x{}",
+            histogram.width(),
+            histogram.height(),
+            output.width,
+            output.height
+        );
+        let data = resample::resample(&mut histogram, output, config.file_buffer_size)?;
+        let counts_per_density = histogram.counts_per_density(image, output.width, output.height)?;
+        let mapping = image.tone_map.prepare(&data, counts_per_density);
+        print_mapping(&mapping);
+
+        data.map_to_gray_as(&mapping, config.format.depth())
+            .save(&band_file_name(config, image))?;
+
+        if let Some(ref layer) = image.layer {
+            composite
+                .get_or_insert_with(|| image::Composite::new(data.width(), data.height()))
+                .add(&data, &mapping, layer)?;
+        }
+    }
+
+    if let Some(composite) = composite {
+        let file_name = composite_file_name(config);
+        println!("Saving composite to {}", file_name);
+        composite
+            .to_image_as(config.format.depth())
+            .save(&file_name)?;
+    }
+
+    Ok(())
+}
+
+struct Band<'a> {
+    image: &'a ImageConfig,
+    histogram: HistogramFile,
+    width: usize,
+    height: usize,
+
+    mapping: Mapping,
+    stream: ImageStream,
+}
+
+/// Renders the histograms at full size in strips of `file_buffer_size` pixels, so neither the
+/// histograms nor the images have to fit into memory.
+fn render_streamed(config: &Config) -> io::Result<()> {
+    let mut bands = vec![];
+    for image in &config.images {
+        println!("Reading statistics of {}", image.file_name);
+        let mut histogram = HistogramFile::open(image)?;
+        let (width, height) = (histogram.width(), histogram.height());
+
+        let statistics = histogram.statistics(config.file_buffer_size)?;
+        let counts_per_density = histogram.counts_per_density(image, width, height)?;
+        let mapping = image
+            .tone_map
+            .prepare_with(statistics.distribution, counts_per_density);
+        print_mapping(&mapping);
+
+        bands.push(Band {
+            image,
+            histogram,
+            width,
+            height,
+            mapping,
+            stream: ImageStream::create(
+                &band_file_name(config, image),
+                config.format,
+                width,
+                height,
+                false,
+            )?,
+        });
+    }
+
+    let layered = bands
+        .iter()
+        .filter(|band| band.image.layer.is_some())
+        .map(|band| (band.width, band.height))
+        .collect::<Vec<_>>();
+    let mut composite = match layered.first() {
+        Some(&size) if layered.iter().any(|other| *other != size) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "All bands of the composite need to have the same size",
+            ))
+        }
+        Some(&(width, height)) => {
+            let file_name = composite_file_name(config);
+            println!("Streaming composite to {}", file_name);
+            Some((
+                ImageStream::create(&file_name, config.format, width, height, true)?,
+                width,
+                height,
+            ))
+        }
+        None => None,
+    };
+
+    let depth = config.format.depth();
+    let widest = bands.iter().map(|band| band.width).max().unwrap_or(1);
+    let highest = bands.iter().map(|band| band.height).max().unwrap_or(0);
+    let strip_rows = (config.file_buffer_size / widest).max(1);
+    let mut buffer = vec::filled_with(0u64, strip_rows * widest);
+
+    let mut row = 0;
+    while row < highest {
+        let mut strip_composite = composite
+            .as_ref()
+            .filter(|&&(_, _, height)| row < height)
+            .map(|&(_, width, height)| {
+                image::Composite::new(width, strip_rows.min(height - row))
+            });
+
+        for band in &mut bands {
+            if row >= band.height {
+                continue;
+            }
+
+            let rows = strip_rows.min(band.height - row);
+            let strip = &mut buffer[..rows * band.width];
+            band.histogram.read((row * band.width) as u64, strip)?;
+            let strip = image::ImageData::from_data(strip.to_vec(), band.width, rows);
+
+            band.stream.write(&strip.map_to_gray_as(&band.mapping, depth))?;
+            if let (Some(ref layer), Some(ref mut composite)) =
+                (&band.image.layer, &mut strip_composite)
+            {
+                composite.add(&strip, &band.mapping, layer)?;
+            }
+        }
+
+        if let (Some((ref mut stream, _, _)), Some(strip_composite)) =
+            (&mut composite, strip_composite)
+        {
+            stream.write(&strip_composite.to_image_as(depth))?;
+        }
+
+        row += strip_rows;
+    }
+
+    for band in bands {
+        band.stream.finish()?;
+    }
+    if let Some((stream, _, _)) = composite {
+        stream.finish()?;
+    }
+
+    Ok(())
+}
+
+fn band_file_name(config: &Config, image: &ImageConfig) -> String {
+    format!("{}.{}", image.file_name, config.format.extension())
+}
+fn composite_file_name(config: &Config) -> String {
+    Path::new(&config.image_file_name)
+        .with_extension(config.format.extension())
+        .to_string_lossy()
+        .into_owned()
+}
+
+pub fn print_mapping(mapping: &Mapping) {
+    println!(
+        "Black point {}, white point {}",
+        mapping.black_point(),
+        mapping.white_point()
+    );
+}
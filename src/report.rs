@@ -0,0 +1,208 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use serde_json;
+
+use config::{Config, Orbits};
+
+/// What happened to a single sample.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Outcome {
+    /// Skipped by the interior test without iterating.
+    Skipped,
+    /// Did not escape within `check_iterations`.
+    Bounded,
+    /// Escaped after this many iterations.
+    Escaped(usize),
+}
+
+/// Distribution of the escape iterations of all samples of a run, used to balance the
+/// iteration bands before a long run.
+///
+/// Samples count as often as their orbits were recorded, so under the Metropolis sampler the
+/// report describes the sampled density rather than the proposals.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct EscapeReport {
+    pub samples: u64,
+    pub skipped: u64,
+    pub bounded: u64,
+    /// Samples by the iteration they escaped in.
+    pub escapes: Vec<u64>,
+}
+
+#[derive(Serialize)]
+struct Summary<'a> {
+    samples: u64,
+    skipped: u64,
+    skipped_fraction: f64,
+    bounded: u64,
+    bounded_fraction: f64,
+    bands: Vec<Band<'a>>,
+    /// Trailing iterations nobody escaped in are left out.
+    escapes: &'a [u64],
+}
+
+#[derive(Serialize)]
+struct Band<'a> {
+    file_name: &'a str,
+    min_iterations: usize,
+    max_iterations: usize,
+    orbits: Orbits,
+    samples: u64,
+    fraction: f64,
+}
+
+impl EscapeReport {
+    pub fn new(check_iterations: usize) -> EscapeReport {
+        EscapeReport {
+            samples: 0,
+            skipped: 0,
+            bounded: 0,
+            escapes: vec![0; check_iterations + 1],
+        }
+    }
+
+    /// Adds `weight` samples with the same outcome.
+    pub fn add(&mut self, outcome: Outcome, weight: u64) {
+        self.samples += weight;
+        match outcome {
+            Outcome::Skipped => self.skipped += weight,
+            Outcome::Bounded => self.bounded += weight,
+            Outcome::Escaped(iterations) => self.escapes[iterations] += weight,
+        }
+    }
+
+    pub fn merge(&mut self, other: &EscapeReport) {
+        self.samples += other.samples;
+        self.skipped += other.skipped;
+        self.bounded += other.bounded;
+        for (count, other) in self.escapes.iter_mut().zip(&other.escapes) {
+            *count += other;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.samples = 0;
+        self.skipped = 0;
+        self.bounded = 0;
+        for count in &mut self.escapes {
+            *count = 0;
+        }
+    }
+
+    /// Samples whose orbits are recorded in the band of an image.
+    fn band_samples(&self, min_iterations: usize, max_iterations: usize, orbits: Orbits) -> u64 {
+        match orbits {
+            Orbits::Escaping => self.escapes[min_iterations..max_iterations].iter().sum(),
+            Orbits::Bounded => self.skipped + self.bounded,
+        }
+    }
+
+    fn fraction(&self, samples: u64) -> f64 {
+        if self.samples == 0 {
+            0.0
+        } else {
+            samples as f64 / self.samples as f64
+        }
+    }
+
+    /// Writes the report as JSON or, for any other extension, as CSV.
+    pub fn write(&self, path: &str, config: &Config) -> io::Result<()> {
+        let json = Path::new(path)
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+
+        let mut file = BufWriter::new(File::create(path)?);
+        if json {
+            self.write_json(&mut file, config)?;
+        } else {
+            self.write_csv(&mut file, config)?;
+        }
+        file.flush()
+    }
+
+    fn write_json<W: Write>(&self, writer: &mut W, config: &Config) -> io::Result<()> {
+        let used = self.escapes.iter().rposition(|&count| count > 0).map_or(0, |i| i + 1);
+        let summary = Summary {
+            samples: self.samples,
+            skipped: self.skipped,
+            skipped_fraction: self.fraction(self.skipped),
+            bounded: self.bounded,
+            bounded_fraction: self.fraction(self.bounded),
+            bands: config
+                .images
+                .iter()
+                .map(|image| {
+                    let samples =
+                        self.band_samples(image.min_iterations, image.max_iterations, image.orbits);
+                    Band {
+                        file_name: &image.file_name,
+                        min_iterations: image.min_iterations,
+                        max_iterations: image.max_iterations,
+                        orbits: image.orbits,
+                        samples,
+                        fraction: self.fraction(samples),
+                    }
+                }).collect(),
+            escapes: &self.escapes[..used],
+        };
+
+        serde_json::to_writer_pretty(&mut *writer, &summary)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        writeln!(writer)
+    }
+
+    /// One row per category: the skipped and bounded samples, every band and every iteration
+    /// up to the last one any sample escaped in, with the cumulative fraction of all samples
+    /// escaping before the end of the row.
+    fn write_csv<W: Write>(&self, writer: &mut W, config: &Config) -> io::Result<()> {
+        writeln!(
+            writer,
+            "category,min_iterations,max_iterations,samples,fraction,cumulative_fraction"
+        )?;
+        writeln!(
+            writer,
+            "skipped,,,{},{},",
+            self.skipped,
+            self.fraction(self.skipped)
+        )?;
+        writeln!(
+            writer,
+            "bounded,,,{},{},",
+            self.bounded,
+            self.fraction(self.bounded)
+        )?;
+        for image in &config.images {
+            let samples =
+                self.band_samples(image.min_iterations, image.max_iterations, image.orbits);
+            writeln!(
+                writer,
+                "{},{},{},{},{},",
+                image.file_name,
+                image.min_iterations,
+                image.max_iterations,
+                samples,
+                self.fraction(samples)
+            )?;
+        }
+
+        let used = self.escapes.iter().rposition(|&count| count > 0).map_or(0, |i| i + 1);
+        let mut cumulative = 0;
+        for (iteration, &count) in self.escapes[..used].iter().enumerate() {
+            cumulative += count;
+            writeln!(
+                writer,
+                "escaped,{},{},{},{},{}",
+                iteration,
+                iteration + 1,
+                count,
+                self.fraction(count),
+                self.fraction(cumulative)
+            )?;
+        }
+
+        Ok(())
+    }
+}
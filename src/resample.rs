@@ -0,0 +1,185 @@
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+use std::io;
+
+use image::{HistogramFile, ImageData};
+use vec;
+
+/// Size of the rendered images when it differs from the histograms.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Output {
+    pub width: usize,
+    pub height: usize,
+    #[serde(default)]
+    pub filter: Filter,
+}
+
+/// Reconstruction filter, measured in pixels of the smaller of both images.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Filter {
+    Box,
+    Gaussian { sigma: f64 },
+    Lanczos { a: u32 },
+}
+impl Default for Filter {
+    fn default() -> Filter {
+        Filter::Lanczos { a: 3 }
+    }
+}
+impl Filter {
+    fn support(&self) -> f64 {
+        match *self {
+            Filter::Box => 0.5,
+            Filter::Gaussian { sigma } => 3.0 * sigma,
+            Filter::Lanczos { a } => f64::from(a),
+        }
+    }
+
+    fn weight(&self, x: f64) -> f64 {
+        match *self {
+            Filter::Box => if x.abs() <= 0.5 {
+                1.0
+            } else {
+                0.0
+            },
+            Filter::Gaussian { sigma } => (-x * x / (2.0 * sigma * sigma)).exp(),
+            Filter::Lanczos { a } => {
+                let a = f64::from(a);
+                if x.abs() >= a {
+                    0.0
+                } else {
+                    sinc(x) * sinc(x / a)
+                }
+            }
+        }
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// Contributions of source pixels to one output pixel along one axis.
+struct Weights {
+    first: usize,
+    weights: Vec<f64>,
+}
+
+/// Computes the weights of all output pixels along an axis. They add up to the number of source
+/// pixels per output pixel, so the total count of the histogram is preserved.
+fn weights(source: usize, output: usize, filter: Filter) -> Vec<Weights> {
+    let scale = source as f64 / output as f64;
+    let stretch = scale.max(1.0);
+    let radius = filter.support() * stretch;
+
+    (0..output)
+        .map(|i| {
+            let center = (i as f64 + 0.5) * scale - 0.5;
+            let first = (center - radius).ceil().max(0.0) as usize;
+            let last = ((center + radius).floor().max(0.0) as usize).min(source - 1);
+
+            let mut weights = (first..=last)
+                .map(|j| filter.weight((j as f64 - center) / stretch))
+                .collect::<Vec<_>>();
+            if weights.is_empty() {
+                // The filter is narrower than the distance to the closest source pixel.
+                return Weights {
+                    first: (center.round().max(0.0) as usize).min(source - 1),
+                    weights: vec![scale],
+                };
+            }
+
+            let sum: f64 = weights.iter().sum();
+            for weight in &mut weights {
+                *weight *= scale / sum;
+            }
+
+            Weights { first, weights }
+        }).collect()
+}
+
+/// Resamples a histogram to the output size, reading `block_size` pixels worth of rows at a
+/// time.
+///
+/// Only the rows in reach of the filter are kept in memory, after they were resampled
+/// horizontally.
+pub fn resample(
+    histogram: &mut HistogramFile,
+    output: &Output,
+    block_size: usize,
+) -> io::Result<ImageData> {
+    let (width, height) = (histogram.width(), histogram.height());
+    let columns = weights(width, output.width, output.filter);
+    let rows = weights(height, output.height, output.filter);
+
+    let block_rows = (block_size / width).max(1);
+    let mut block = vec::filled_with(0u64, block_rows * width);
+    let mut block_start = 0;
+    let mut block_end = 0;
+
+    // Horizontally resampled source rows, starting at source row `window_start`.
+    let mut window = VecDeque::<Vec<f64>>::new();
+    let mut window_start = 0;
+
+    let mut data = Vec::with_capacity(output.width * output.height);
+    let mut row = vec::filled_with(0.0, output.width);
+    for row_weights in &rows {
+        let last = row_weights.first + row_weights.weights.len();
+
+        while window_start < row_weights.first && !window.is_empty() {
+            window.pop_front();
+            window_start += 1;
+        }
+        if window.is_empty() {
+            window_start = row_weights.first;
+        }
+
+        while window_start + window.len() < last {
+            let source_row = window_start + window.len();
+            if source_row >= block_end {
+                block_start = source_row;
+                block_end = (block_start + block_rows).min(height);
+                histogram.read(
+                    (block_start * width) as u64,
+                    &mut block[..(block_end - block_start) * width],
+                )?;
+            }
+
+            let source = &block[(source_row - block_start) * width..][..width];
+            window.push_back(
+                columns
+                    .iter()
+                    .map(|column| {
+                        column
+                            .weights
+                            .iter()
+                            .zip(&source[column.first..])
+                            .map(|(weight, value)| weight * *value as f64)
+                            .sum()
+                    }).collect(),
+            );
+        }
+
+        row.fill(0.0);
+        for (weight, source) in row_weights
+            .weights
+            .iter()
+            .zip(window.iter().skip(row_weights.first - window_start))
+        {
+            for (value, source) in row.iter_mut().zip(source) {
+                *value += weight * source;
+            }
+        }
+
+        // Negative lobes of the Lanczos filter can undershoot next to bright pixels.
+        data.extend(row.iter().map(|value| value.max(0.0).round() as u64));
+    }
+
+    Ok(ImageData::from_data(data, output.width, output.height))
+}
@@ -0,0 +1,328 @@
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+use num::complex::Complex64;
+
+use math;
+use math::CYCLE_TOLERANCE;
+
+/// Points iterated at once.
+pub const LANES: usize = 4;
+
+/// Implementation of the batched z^2 + c iteration.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kernel {
+    /// Plain arrays of lanes, available everywhere.
+    Lanes,
+    /// Explicit AVX2 instructions.
+    Avx2,
+}
+impl Kernel {
+    /// The fastest kernel the CPU supports.
+    pub fn detect() -> Kernel {
+        if Kernel::Avx2.is_available() {
+            Kernel::Avx2
+        } else {
+            Kernel::Lanes
+        }
+    }
+
+    pub fn is_available(self) -> bool {
+        match self {
+            Kernel::Lanes => true,
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Avx2 => is_x86_feature_detected!("avx2"),
+            #[cfg(not(target_arch = "x86_64"))]
+            Kernel::Avx2 => false,
+        }
+    }
+}
+
+/// Bailout area and iteration limit shared by all lanes.
+struct Bounds {
+    initial: Complex64,
+    min: Complex64,
+    max: Complex64,
+    max_iterations: u64,
+}
+
+/// Hands out the points to the lanes and keeps track of which point every lane iterates.
+struct Feeder<'a> {
+    cs: &'a [Complex64],
+    next: usize,
+    points: [Option<usize>; LANES],
+}
+impl<'a> Feeder<'a> {
+    fn new(cs: &'a [Complex64]) -> Feeder<'a> {
+        Feeder {
+            cs,
+            next: 0,
+            points: [None; LANES],
+        }
+    }
+
+    /// Assigns the next point to `lane`. Lanes without a point left iterate the fixed point 0
+    /// without being active.
+    fn feed(&mut self, lane: usize) -> Complex64 {
+        match self.cs.get(self.next) {
+            Some(&c) => {
+                self.points[lane] = Some(self.next);
+                self.next += 1;
+                c
+            }
+            None => {
+                self.points[lane] = None;
+                Complex64::new(0.0, 0.0)
+            }
+        }
+    }
+
+    fn active(&self, lane: usize) -> bool {
+        self.points[lane].is_some()
+    }
+
+    fn done(&self) -> bool {
+        self.points.iter().all(|point| point.is_none())
+    }
+
+    /// Stores the result of the point in `lane` and assigns the next one.
+    fn finish(
+        &mut self,
+        lane: usize,
+        result: Option<usize>,
+        results: &mut [Option<usize>],
+    ) -> Complex64 {
+        if let Some(point) = self.points[lane] {
+            results[point] = result;
+        }
+        self.feed(lane)
+    }
+}
+
+/// Computes `math::calculate_bailout_iteration` of z^2 + c for every point of `cs`, with
+/// identical results. Whenever a lane finishes, it continues with the next point, so lanes
+/// taking different numbers of iterations do not wait for each other.
+pub fn quadratic_bailout_iterations(
+    kernel: Kernel,
+    cs: &[Complex64],
+    initial: Complex64,
+    bailout_min: Complex64,
+    bailout_max: Complex64,
+    max_iterations: usize,
+    results: &mut [Option<usize>],
+) {
+    // All lanes share the initial value, so it either starts outside for all or for none.
+    if !math::complex_between(bailout_min, initial, bailout_max) || max_iterations == 0 {
+        let result = if math::complex_between(bailout_min, initial, bailout_max) {
+            None
+        } else {
+            Some(0)
+        };
+        for value in results.iter_mut().take(cs.len()) {
+            *value = result;
+        }
+        return;
+    }
+
+    let bounds = Bounds {
+        initial,
+        min: bailout_min,
+        max: bailout_max,
+        max_iterations: max_iterations as u64,
+    };
+    match kernel {
+        #[cfg(target_arch = "x86_64")]
+        Kernel::Avx2 if kernel.is_available() => unsafe { run_avx2(cs, &bounds, results) },
+        _ => run_lanes(cs, &bounds, results),
+    }
+}
+
+/// Lanes in arrays, following the cycle detection of `math::calculate_bailout_iteration`.
+fn run_lanes(cs: &[Complex64], bounds: &Bounds, results: &mut [Option<usize>]) {
+    let mut feeder = Feeder::new(cs);
+
+    let mut z_re = [bounds.initial.re; LANES];
+    let mut z_im = [bounds.initial.im; LANES];
+    let mut c_re = [0.0; LANES];
+    let mut c_im = [0.0; LANES];
+    let mut saved_re = [bounds.initial.re; LANES];
+    let mut saved_im = [bounds.initial.im; LANES];
+    let mut steps = [0u64; LANES];
+    let mut power = [1u64; LANES];
+    let mut iterations = [0u64; LANES];
+    for lane in 0..LANES {
+        let c = feeder.feed(lane);
+        c_re[lane] = c.re;
+        c_im[lane] = c.im;
+    }
+
+    while !feeder.done() {
+        for lane in 0..LANES {
+            let (re, im) = (z_re[lane], z_im[lane]);
+            let new_re = re * re - im * im + c_re[lane];
+            let new_im = re * im + im * re + c_im[lane];
+            z_re[lane] = new_re;
+            z_im[lane] = new_im;
+            iterations[lane] += 1;
+            steps[lane] += 1;
+
+            let (d_re, d_im) = (new_re - saved_re[lane], new_im - saved_im[lane]);
+            let cycle = d_re * d_re + d_im * d_im < CYCLE_TOLERANCE;
+            if steps[lane] == power[lane] {
+                saved_re[lane] = new_re;
+                saved_im[lane] = new_im;
+                power[lane] *= 2;
+                steps[lane] = 0;
+            }
+
+            let z = Complex64::new(new_re, new_im);
+            let inside = math::complex_between(bounds.min, z, bounds.max);
+            let result = if cycle {
+                None
+            } else if !inside {
+                Some(iterations[lane] as usize)
+            } else if iterations[lane] == bounds.max_iterations {
+                None
+            } else {
+                continue;
+            };
+            if !feeder.active(lane) {
+                continue;
+            }
+
+            let c = feeder.finish(lane, result, results);
+            z_re[lane] = bounds.initial.re;
+            z_im[lane] = bounds.initial.im;
+            c_re[lane] = c.re;
+            c_im[lane] = c.im;
+            saved_re[lane] = bounds.initial.re;
+            saved_im[lane] = bounds.initial.im;
+            steps[lane] = 0;
+            power[lane] = 1;
+            iterations[lane] = 0;
+        }
+    }
+}
+
+/// Same as `run_lanes` with all lanes in AVX2 registers. Finished lanes are reset in the
+/// registers; only their new points go through memory.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn run_avx2(cs: &[Complex64], bounds: &Bounds, results: &mut [Option<usize>]) {
+    let mut feeder = Feeder::new(cs);
+
+    let mut c_re = [0.0; LANES];
+    let mut c_im = [0.0; LANES];
+    let mut active = [0i64; LANES];
+    for lane in 0..LANES {
+        let c = feeder.feed(lane);
+        c_re[lane] = c.re;
+        c_im[lane] = c.im;
+        active[lane] = if feeder.active(lane) { -1 } else { 0 };
+    }
+
+    let initial_re = _mm256_set1_pd(bounds.initial.re);
+    let initial_im = _mm256_set1_pd(bounds.initial.im);
+    let min_re = _mm256_set1_pd(bounds.min.re);
+    let min_im = _mm256_set1_pd(bounds.min.im);
+    let max_re = _mm256_set1_pd(bounds.max.re);
+    let max_im = _mm256_set1_pd(bounds.max.im);
+    let max_iterations = _mm256_set1_epi64x(bounds.max_iterations as i64);
+    let tolerance = _mm256_set1_pd(CYCLE_TOLERANCE);
+    let zero = _mm256_setzero_si256();
+    let one = _mm256_set1_epi64x(1);
+
+    let mut z_re = initial_re;
+    let mut z_im = initial_im;
+    let mut saved_re = initial_re;
+    let mut saved_im = initial_im;
+    let mut steps = zero;
+    let mut power = one;
+    let mut iterations = zero;
+    let mut c_re_lanes = _mm256_loadu_pd(c_re.as_ptr());
+    let mut c_im_lanes = _mm256_loadu_pd(c_im.as_ptr());
+    let mut active_lanes =
+        _mm256_castsi256_pd(_mm256_loadu_si256(active.as_ptr() as *const __m256i));
+
+    while !feeder.done() {
+        let new_re = _mm256_add_pd(
+            _mm256_sub_pd(_mm256_mul_pd(z_re, z_re), _mm256_mul_pd(z_im, z_im)),
+            c_re_lanes,
+        );
+        let new_im = _mm256_add_pd(
+            _mm256_add_pd(_mm256_mul_pd(z_re, z_im), _mm256_mul_pd(z_im, z_re)),
+            c_im_lanes,
+        );
+        z_re = new_re;
+        z_im = new_im;
+        iterations = _mm256_add_epi64(iterations, one);
+        steps = _mm256_add_epi64(steps, one);
+
+        let d_re = _mm256_sub_pd(new_re, saved_re);
+        let d_im = _mm256_sub_pd(new_im, saved_im);
+        let cycle = _mm256_cmp_pd(
+            _mm256_add_pd(_mm256_mul_pd(d_re, d_re), _mm256_mul_pd(d_im, d_im)),
+            tolerance,
+            _CMP_LT_OQ,
+        );
+        let save = _mm256_cmpeq_epi64(steps, power);
+        saved_re = _mm256_blendv_pd(saved_re, new_re, _mm256_castsi256_pd(save));
+        saved_im = _mm256_blendv_pd(saved_im, new_im, _mm256_castsi256_pd(save));
+        power = _mm256_blendv_epi8(power, _mm256_add_epi64(power, power), save);
+        steps = _mm256_blendv_epi8(steps, zero, save);
+
+        // NaN compares false, so it counts as outside like in `math::complex_between`.
+        let inside = _mm256_and_pd(
+            _mm256_and_pd(
+                _mm256_cmp_pd(min_re, new_re, _CMP_LT_OQ),
+                _mm256_cmp_pd(new_re, max_re, _CMP_LT_OQ),
+            ),
+            _mm256_and_pd(
+                _mm256_cmp_pd(min_im, new_im, _CMP_LT_OQ),
+                _mm256_cmp_pd(new_im, max_im, _CMP_LT_OQ),
+            ),
+        );
+        let limit = _mm256_castsi256_pd(_mm256_cmpeq_epi64(iterations, max_iterations));
+        let finished = _mm256_and_pd(
+            _mm256_or_pd(_mm256_or_pd(cycle, _mm256_andnot_pd(inside, active_lanes)), limit),
+            active_lanes,
+        );
+
+        let finished_mask = _mm256_movemask_pd(finished);
+        if finished_mask == 0 {
+            continue;
+        }
+
+        let cycle_mask = _mm256_movemask_pd(cycle);
+        let inside_mask = _mm256_movemask_pd(inside);
+        let mut lane_iterations = [0u64; LANES];
+        _mm256_storeu_si256(lane_iterations.as_mut_ptr() as *mut __m256i, iterations);
+        _mm256_storeu_pd(c_re.as_mut_ptr(), c_re_lanes);
+        _mm256_storeu_pd(c_im.as_mut_ptr(), c_im_lanes);
+
+        for lane in (0..LANES).filter(|lane| finished_mask & (1 << lane) != 0) {
+            let result = if cycle_mask & (1 << lane) != 0 || inside_mask & (1 << lane) != 0 {
+                None
+            } else {
+                Some(lane_iterations[lane] as usize)
+            };
+            let c = feeder.finish(lane, result, results);
+            c_re[lane] = c.re;
+            c_im[lane] = c.im;
+            active[lane] = if feeder.active(lane) { -1 } else { 0 };
+        }
+
+        c_re_lanes = _mm256_loadu_pd(c_re.as_ptr());
+        c_im_lanes = _mm256_loadu_pd(c_im.as_ptr());
+        active_lanes =
+            _mm256_castsi256_pd(_mm256_loadu_si256(active.as_ptr() as *const __m256i));
+        let reset = _mm256_castpd_si256(finished);
+        z_re = _mm256_blendv_pd(z_re, initial_re, finished);
+        z_im = _mm256_blendv_pd(z_im, initial_im, finished);
+        saved_re = _mm256_blendv_pd(saved_re, initial_re, finished);
+        saved_im = _mm256_blendv_pd(saved_im, initial_im, finished);
+        steps = _mm256_blendv_epi8(steps, zero, reset);
+        power = _mm256_blendv_epi8(power, one, reset);
+        iterations = _mm256_blendv_epi8(iterations, zero, reset);
+    }
+}
@@ -0,0 +1,300 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use config::Config;
+use image::{Composite, HistogramFile, Image, ImageData, Layer};
+use render;
+use tone_map::Mapping;
+use vec;
+
+/// A histogram feeding a pyramid, read strip by strip.
+struct Band<'a> {
+    histogram: HistogramFile,
+    mapping: &'a Mapping,
+    /// Bands without a layer are rendered in gray, which only works for a single band.
+    layer: Option<&'a Layer>,
+}
+
+/// One zoom level, collecting rows until a full row of tiles can be written.
+struct Level {
+    /// DeepZoom level, 0 being a single pixel.
+    index: usize,
+    width: usize,
+    height: usize,
+
+    /// Rows of the current row of tiles, one buffer per band.
+    rows: Vec<Vec<u64>>,
+    received: usize,
+    /// Every even row waits here for the odd one below before both are passed down.
+    pending: Option<Vec<Vec<u64>>>,
+}
+
+struct Pyramid<'a> {
+    directory: PathBuf,
+    tile_size: usize,
+    bands: Vec<Band<'a>>,
+    levels: Vec<Level>,
+}
+
+/// Exports every histogram of the config, and the composite of all bands with a layer, as a
+/// DeepZoom tile pyramid next to the images `render` would write.
+///
+/// Every level is downsampled from the one above by averaging 2x2 blocks of counts, so the
+/// tone mapping of the full size histogram fits all levels.
+pub fn export(config: &Config, tile_size: usize) -> io::Result<()> {
+    let mut mappings = vec![];
+    for image in &config.images {
+        println!("Reading statistics of {}", image.file_name);
+        let mut histogram = HistogramFile::open(image)?;
+        let (width, height) = (histogram.width(), histogram.height());
+        let statistics = histogram.statistics(config.file_buffer_size)?;
+        // Levels keep the counts of the full size histogram, so its densities hold for all.
+        let counts_per_density = histogram.counts_per_density(image, width, height)?;
+        let mapping = image
+            .tone_map
+            .prepare_with(statistics.distribution, counts_per_density);
+        render::print_mapping(&mapping);
+        mappings.push((mapping, width, height));
+    }
+
+    for (image, &(ref mapping, width, height)) in config.images.iter().zip(&mappings) {
+        let band = Band {
+            histogram: HistogramFile::open(image)?,
+            mapping,
+            layer: None,
+        };
+        write(
+            &image.file_name,
+            vec![band],
+            width,
+            height,
+            tile_size,
+            config.file_buffer_size,
+        )?;
+    }
+
+    let mut layered = vec![];
+    for (image, &(ref mapping, width, height)) in config.images.iter().zip(&mappings) {
+        if let Some(ref layer) = image.layer {
+            let band = Band {
+                histogram: HistogramFile::open(image)?,
+                mapping,
+                layer: Some(layer),
+            };
+            layered.push((band, width, height));
+        }
+    }
+    if let Some(&(_, width, height)) = layered.first() {
+        if layered.iter().any(|&(_, w, h)| (w, h) != (width, height)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "All bands of the composite need to have the same size",
+            ));
+        }
+
+        let bands = layered.into_iter().map(|(band, _, _)| band).collect();
+        write(
+            &config.image_file_name,
+            bands,
+            width,
+            height,
+            tile_size,
+            config.file_buffer_size,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Writes `<name>.dzi` and the tiles in `<name>_files/<level>/<column>_<row>.png`, with the
+/// extension of `name` removed.
+fn write(
+    name: &str,
+    bands: Vec<Band>,
+    width: usize,
+    height: usize,
+    tile_size: usize,
+    block_size: usize,
+) -> io::Result<()> {
+    let base = Path::new(name).with_extension("");
+    let descriptor = base.with_extension("dzi");
+    let mut directory = base.into_os_string();
+    directory.push("_files");
+    let directory = PathBuf::from(directory);
+
+    let mut sizes = vec![(width, height)];
+    while let Some(&(width, height)) = sizes.last() {
+        if width <= 1 && height <= 1 {
+            break;
+        }
+        sizes.push((width.div_ceil(2), height.div_ceil(2)));
+    }
+    let levels = sizes
+        .iter()
+        .enumerate()
+        .map(|(i, &(width, height))| Level {
+            index: sizes.len() - 1 - i,
+            width,
+            height,
+            rows: bands.iter().map(|_| vec![]).collect(),
+            received: 0,
+            pending: None,
+        }).collect::<Vec<Level>>();
+
+    println!(
+        "Writing {} levels of tiles to {}",
+        levels.len(),
+        directory.display()
+    );
+    for level in &levels {
+        fs::create_dir_all(directory.join(level.index.to_string()))?;
+    }
+
+    let mut pyramid = Pyramid {
+        directory,
+        tile_size,
+        bands,
+        levels,
+    };
+
+    let strip_rows = (block_size / width).max(1);
+    let mut buffer = vec::filled_with(0u64, strip_rows * width);
+    let mut row = 0;
+    while row < height {
+        let rows = strip_rows.min(height - row);
+        let mut strips = vec![];
+        for band in &mut pyramid.bands {
+            let strip = &mut buffer[..rows * width];
+            band.histogram.read((row * width) as u64, strip)?;
+            strips.push(strip.to_vec());
+        }
+
+        for i in 0..rows {
+            let source = strips
+                .iter()
+                .map(|strip| strip[i * width..][..width].to_vec())
+                .collect();
+            pyramid.push(0, source)?;
+        }
+        row += rows;
+    }
+
+    let mut descriptor = File::create(descriptor)?;
+    writeln!(descriptor, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        descriptor,
+        r#"<Image xmlns="http://schemas.microsoft.com/deepzoom/2008" TileSize="{}" Overlap="0" Format="png">"#,
+        tile_size
+    )?;
+    writeln!(descriptor, r#"  <Size Width="{}" Height="{}"/>"#, width, height)?;
+    writeln!(descriptor, "</Image>")?;
+
+    Ok(())
+}
+
+impl<'a> Pyramid<'a> {
+    /// Adds the next row of every band to level `i` and passes it on to the coarser levels.
+    fn push(&mut self, i: usize, row: Vec<Vec<u64>>) -> io::Result<()> {
+        let last = {
+            let level = &mut self.levels[i];
+            for (rows, band) in level.rows.iter_mut().zip(&row) {
+                rows.extend_from_slice(band);
+            }
+            level.received += 1;
+            level.received == level.height
+        };
+        if self.levels[i].received.is_multiple_of(self.tile_size) || last {
+            self.write_tiles(i)?;
+        }
+
+        if i + 1 < self.levels.len() {
+            match self.levels[i].pending.take() {
+                None if !last => self.levels[i].pending = Some(row),
+                pending => {
+                    let width = self.levels[i].width;
+                    let downsampled = row
+                        .iter()
+                        .enumerate()
+                        .map(|(band, lower)| {
+                            downsample(pending.as_ref().map(|upper| &upper[band][..]), lower, width)
+                        }).collect();
+                    self.push(i + 1, downsampled)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the buffered rows of level `i` as one row of tiles.
+    fn write_tiles(&mut self, i: usize) -> io::Result<()> {
+        let level = &mut self.levels[i];
+        let rows = level.rows[0].len() / level.width;
+        let tile_row = (level.received - 1) / self.tile_size;
+        let directory = self.directory.join(level.index.to_string());
+
+        let mut column = 0;
+        while column * self.tile_size < level.width {
+            let x = column * self.tile_size;
+            let width = self.tile_size.min(level.width - x);
+
+            let tiles = level
+                .rows
+                .iter()
+                .map(|band| {
+                    let mut data = Vec::with_capacity(width * rows);
+                    for row in band.chunks(level.width) {
+                        data.extend_from_slice(&row[x..x + width]);
+                    }
+                    ImageData::from_data(data, width, rows)
+                }).collect::<Vec<_>>();
+
+            render_tile(&self.bands, &tiles)?.save(
+                &directory
+                    .join(format!("{}_{}.png", column, tile_row))
+                    .to_string_lossy(),
+            )?;
+            column += 1;
+        }
+
+        for band in &mut level.rows {
+            band.clear();
+        }
+        Ok(())
+    }
+}
+
+fn render_tile(bands: &[Band], tiles: &[ImageData]) -> io::Result<Image> {
+    if bands[0].layer.is_none() {
+        return Ok(tiles[0].map_to_gray(bands[0].mapping));
+    }
+
+    let mut composite = Composite::new(tiles[0].width(), tiles[0].height());
+    for (band, tile) in bands.iter().zip(tiles) {
+        if let Some(layer) = band.layer {
+            composite.add(tile, band.mapping, layer)?;
+        }
+    }
+    Ok(composite.to_image())
+}
+
+/// Averages 2x2 blocks of counts. At the right and bottom edge of odd sized levels, fewer
+/// pixels are averaged.
+fn downsample(upper: Option<&[u64]>, lower: &[u64], width: usize) -> Vec<u64> {
+    (0..width.div_ceil(2))
+        .map(|x| {
+            let columns = 2 * x..(2 * x + 2).min(width);
+            let mut sum = 0u128;
+            let mut pixels = 0u128;
+            for row in upper.into_iter().chain(Some(lower)) {
+                for value in &row[columns.clone()] {
+                    sum += u128::from(*value);
+                    pixels += 1;
+                }
+            }
+            (sum as f64 / pixels as f64).round() as u64
+        }).collect()
+}
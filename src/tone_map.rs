@@ -0,0 +1,260 @@
+use num;
+
+use image::ImageData;
+
+/// Counts below this are kept exactly.
+const EXACT: u64 = 1 << EXACT_BITS;
+const EXACT_BITS: u64 = 12;
+/// Every power of two above `EXACT` is split into this many buckets, which bounds the relative
+/// error of quantiles to 1/256.
+const SUBDIVISIONS: u64 = 1 << SUBDIVISION_BITS;
+const SUBDIVISION_BITS: u64 = 8;
+const BUCKETS: usize = (EXACT + (64 - EXACT_BITS) * SUBDIVISIONS) as usize;
+
+/// Maps histogram counts to brightness between 0 and 1.
+///
+/// Counts at or below the black point become 0, counts at or above the white point 1. The
+/// operator shapes everything in between.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ToneMap {
+    pub operator: Operator,
+
+    #[serde(default)]
+    pub black_point: u64,
+    /// Defaults to the highest count of the image.
+    #[serde(default)]
+    pub white_point: Option<u64>,
+
+    /// Sets the black point to this percentile of the counts of all hit pixels, unless it is
+    /// given explicitly.
+    #[serde(default)]
+    pub black_percentile: Option<f64>,
+    /// Sets the white point to this percentile, for example 99.9 to clip the brightest 0.1% of
+    /// pixels, unless it is given explicitly.
+    #[serde(default)]
+    pub white_percentile: Option<f64>,
+
+    /// Sets the black point to this density in hits per sample per unit area of the complex
+    /// plane, unless it is given explicitly. Takes precedence over `black_percentile`.
+    #[serde(default)]
+    pub black_density: Option<f64>,
+    /// Sets the white point to this density, unless it is given explicitly. Takes precedence
+    /// over `white_percentile`. Renders of any sample count and resolution then get the same
+    /// brightness, except with the logarithmic operator, which depends on the absolute counts.
+    #[serde(default)]
+    pub white_density: Option<f64>,
+}
+impl Default for ToneMap {
+    fn default() -> ToneMap {
+        ToneMap {
+            operator: Operator::Exponential { k: 2.0 },
+            black_point: 0,
+            white_point: None,
+            black_percentile: None,
+            white_percentile: None,
+            black_density: None,
+            white_density: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Operator {
+    Linear,
+    /// x^gamma
+    Gamma { gamma: f64 },
+    /// ln(1 + count) relative to the white point
+    Logarithmic,
+    Sqrt,
+    /// 1 - e^(-k x), scaled so the white point stays at 1
+    Exponential { k: f64 },
+    /// Histogram equalization: every brightness is used by the same number of pixels.
+    Equalize,
+}
+
+impl ToneMap {
+    /// Whether black or white point are given as densities, which requires the sample count of
+    /// the histogram.
+    pub fn uses_density(&self) -> bool {
+        self.black_density.is_some() || self.white_density.is_some()
+    }
+
+    pub fn prepare(&self, image: &ImageData, counts_per_density: Option<f64>) -> Mapping {
+        self.prepare_with(image.distribution(), counts_per_density)
+    }
+
+    /// Resolves black and white point from the distribution of counts of an image.
+    /// `counts_per_density` converts densities to counts and is needed if `uses_density`.
+    pub fn prepare_with(
+        &self,
+        distribution: Distribution,
+        counts_per_density: Option<f64>,
+    ) -> Mapping {
+        let counts = |density: Option<f64>| match (density, counts_per_density) {
+            (Some(density), Some(scale)) => {
+                Some((density * scale).round().max(0.0) as u64)
+            }
+            _ => None,
+        };
+
+        let white_point = self.white_point.unwrap_or_else(|| {
+            match (counts(self.white_density), self.white_percentile) {
+                (Some(white_point), _) => white_point.max(1),
+                (None, Some(percentile)) => distribution.quantile(percentile / 100.0),
+                (None, None) => distribution.highest(),
+            }
+        });
+        let black_point = match (counts(self.black_density), self.black_percentile) {
+            _ if self.black_point != 0 => self.black_point,
+            (Some(black_point), _) => black_point,
+            (None, Some(percentile)) => distribution.quantile(percentile / 100.0),
+            (None, None) => 0,
+        };
+        let black_point = black_point.min(white_point.saturating_sub(1));
+
+        Mapping {
+            operator: self.operator,
+            black_point,
+            white_point,
+            distribution,
+        }
+    }
+}
+
+/// A `ToneMap` prepared for one image.
+pub struct Mapping {
+    operator: Operator,
+    black_point: u64,
+    white_point: u64,
+
+    distribution: Distribution,
+}
+impl Mapping {
+    pub fn black_point(&self) -> u64 {
+        self.black_point
+    }
+    pub fn white_point(&self) -> u64 {
+        self.white_point
+    }
+
+    pub fn apply(&self, count: u64) -> f64 {
+        if count <= self.black_point {
+            return 0.0;
+        }
+        if count >= self.white_point {
+            return 1.0;
+        }
+
+        let range = (self.white_point - self.black_point) as f64;
+        let x = (count - self.black_point) as f64 / range;
+
+        match self.operator {
+            Operator::Linear => x,
+            Operator::Gamma { gamma } => x.powf(gamma),
+            Operator::Logarithmic => (x * range).ln_1p() / range.ln_1p(),
+            Operator::Sqrt => x.sqrt(),
+            Operator::Exponential { k } => (1.0 - (-k * x).exp()) / (1.0 - (-k).exp()),
+            Operator::Equalize => {
+                let black = self.distribution.cdf(self.black_point);
+                let white = self.distribution.cdf(self.white_point);
+                (self.distribution.cdf(count) - black) / (white - black)
+            }
+        }
+    }
+}
+
+/// Approximate distribution of the counts of a histogram, built in a single streaming pass.
+///
+/// Small counts are tracked exactly; larger ones in logarithmic buckets.
+#[derive(Clone)]
+pub struct Distribution {
+    buckets: Vec<u64>,
+    highest: u64,
+    nonzero: u64,
+}
+impl Distribution {
+    pub fn new() -> Distribution {
+        Distribution {
+            buckets: vec![0; BUCKETS],
+            highest: 0,
+            nonzero: 0,
+        }
+    }
+
+    pub fn add<T: Copy + Into<u64>>(&mut self, values: &[T]) {
+        for &value in values {
+            let value = value.into();
+            self.buckets[bucket(value)] += 1;
+            self.highest = self.highest.max(value);
+            if value > 0 {
+                self.nonzero += 1;
+            }
+        }
+    }
+
+    pub fn highest(&self) -> u64 {
+        self.highest
+    }
+
+    /// The count below which the fraction `q` of all hit pixels lies.
+    pub fn quantile(&self, q: f64) -> u64 {
+        let target = (num::clamp(q, 0.0, 1.0) * self.nonzero as f64).ceil() as u64;
+
+        let mut below = 0;
+        for (i, &count) in self.buckets.iter().enumerate().skip(1) {
+            if below + count >= target && count > 0 {
+                let (start, end) = bounds(i);
+                let fraction = (target - below) as f64 / count as f64;
+                let value = start as f64 + fraction * (end - start) as f64;
+                return (value.round() as u64).min(self.highest);
+            }
+            below += count;
+        }
+
+        self.highest
+    }
+
+    /// The fraction of hit pixels with a count of at most `value`.
+    pub fn cdf(&self, value: u64) -> f64 {
+        if self.nonzero == 0 || value == 0 {
+            return 0.0;
+        }
+
+        let i = bucket(value);
+        let below: u64 = self.buckets[1..i].iter().sum();
+        let (start, end) = bounds(i);
+        let within = ((value - start) as f64 + 1.0) / ((end - start) as f64 + 1.0);
+
+        (below as f64 + within * self.buckets[i] as f64) / self.nonzero as f64
+    }
+}
+impl Default for Distribution {
+    fn default() -> Distribution {
+        Distribution::new()
+    }
+}
+
+fn bucket(value: u64) -> usize {
+    if value < EXACT {
+        return value as usize;
+    }
+
+    let power = 63 - u64::from(value.leading_zeros());
+    let subdivision = (value >> (power - SUBDIVISION_BITS)) & (SUBDIVISIONS - 1);
+    (EXACT + (power - EXACT_BITS) * SUBDIVISIONS + subdivision) as usize
+}
+/// The smallest and largest value falling into a bucket.
+fn bounds(bucket: usize) -> (u64, u64) {
+    let bucket = bucket as u64;
+    if bucket < EXACT {
+        return (bucket, bucket);
+    }
+
+    let power = (bucket - EXACT) / SUBDIVISIONS + EXACT_BITS;
+    let subdivision = (bucket - EXACT) % SUBDIVISIONS;
+    let width = 1u64 << (power - SUBDIVISION_BITS);
+    let start = (1u64 << power) + subdivision * width;
+    (start, start + (width - 1))
+}
@@ -0,0 +1,113 @@
+extern crate mandelbuddha;
+extern crate num;
+extern crate rand;
+
+use std::f64::consts::PI;
+
+use mandelbuddha::math::is_inside_mandelbrot_bulb;
+use num::complex::Complex64;
+use rand::prng::XorShiftRng;
+use rand::{Rng, SeedableRng};
+
+fn escapes(c: Complex64, iterations: usize) -> bool {
+    let mut z = Complex64::new(0.0, 0.0);
+    for _ in 0..iterations {
+        z = z * z + c;
+        if z.norm_sqr() > 4.0 {
+            return true;
+        }
+    }
+    false
+}
+
+fn random_point(rng: &mut XorShiftRng) -> Complex64 {
+    Complex64::new(rng.gen_range(-2.0, 2.0), rng.gen_range(-2.0, 2.0))
+}
+
+#[test]
+fn rejected_points_never_escape() {
+    let mut rng = XorShiftRng::seed_from_u64(1);
+
+    let mut rejected = 0;
+    for _ in 0..20_000 {
+        let c = random_point(&mut rng);
+        if is_inside_mandelbrot_bulb(c) {
+            rejected += 1;
+            assert!(!escapes(c, 10_000), "{} escapes", c);
+        }
+    }
+    // The Mandelbrot set covers about 9% of the square, most of it in the cardioid and bulbs.
+    assert!(rejected > 20_000 * 8 / 100, "only {} points were rejected", rejected);
+}
+
+/// Points inside the cardioid and every bulb the test covers.
+const NUCLEI: [(f64, f64); 11] = [
+    (0.0, 0.0),
+    (-1.0, 0.0),
+    (-0.1226, 0.7449),
+    (-0.1226, -0.7449),
+    (0.2823, 0.5301),
+    (0.2823, -0.5301),
+    (-1.3107, 0.0),
+    (-0.5043, 0.5628),
+    (-0.5043, -0.5628),
+    (0.3795, 0.3349),
+    (0.3795, -0.3349),
+];
+
+/// Bisects the segment for the last rejected point before the edge of the rejected region.
+fn edge(inside: Complex64, outside: Complex64) -> Complex64 {
+    let (mut low, mut high) = (0.0, 1.0);
+    for _ in 0..50 {
+        let middle = (low + high) / 2.0;
+        if is_inside_mandelbrot_bulb(inside + (outside - inside) * middle) {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    inside + (outside - inside) * low
+}
+
+/// Escaping points next to the rejected region escape slowest, so the edges are checked with
+/// many iterations.
+#[test]
+fn edges_of_rejected_region_never_escape() {
+    let mut rng = XorShiftRng::seed_from_u64(2);
+
+    let mut edges = vec![];
+    for &(re, im) in &NUCLEI {
+        let nucleus = Complex64::new(re, im);
+        for i in 0..32 {
+            let direction = Complex64::from_polar(&0.5, &(f64::from(i) * PI / 16.0));
+            edges.push(edge(nucleus, nucleus + direction));
+        }
+    }
+    while edges.len() < NUCLEI.len() * 32 + 200 {
+        let (inside, outside) = (random_point(&mut rng), random_point(&mut rng));
+        if is_inside_mandelbrot_bulb(inside) && !is_inside_mandelbrot_bulb(outside) {
+            edges.push(edge(inside, outside));
+        }
+    }
+
+    for c in edges {
+        assert!(is_inside_mandelbrot_bulb(c));
+        assert!(!escapes(c, 100_000), "{} escapes", c);
+    }
+}
+
+#[test]
+fn rejects_every_covered_component() {
+    for &(re, im) in &NUCLEI {
+        assert!(is_inside_mandelbrot_bulb(Complex64::new(re, im)), "{} {}", re, im);
+    }
+}
+
+#[test]
+fn keeps_points_outside_of_the_set() {
+    for &(re, im) in &[(0.3, 0.0), (-0.75, 0.1), (-2.0, 0.1), (0.0, 1.1), (0.26, 0.0)] {
+        let c = Complex64::new(re, im);
+        assert!(escapes(c, 10_000));
+        assert!(!is_inside_mandelbrot_bulb(c), "{} {}", re, im);
+    }
+}
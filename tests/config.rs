@@ -0,0 +1,38 @@
+extern crate mandelbuddha;
+
+use std::path::Path;
+
+use mandelbuddha::config::Config;
+
+/// Where `set_output_dir("output")` is expected to move `path`.
+fn moved(path: &str) -> String {
+    let file_name = Path::new(path).file_name().unwrap();
+    Path::new("output")
+        .join(file_name)
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[test]
+fn output_dir_moves_all_output_files() {
+    let mut original = Config::default_preset();
+    original.report_file = Some("reports/escape_times.csv".to_owned());
+    let mut config = original.clone();
+    config.set_output_dir("output");
+
+    for (image, original) in config.images.iter().zip(&original.images) {
+        assert_eq!(image.file_name, moved(&original.file_name));
+    }
+    assert_eq!(config.image_file_name, moved(&original.image_file_name));
+    assert_eq!(config.checkpoint_file, moved(&original.checkpoint_file));
+    assert_eq!(config.report_file, Some(moved("escape_times.csv")));
+}
+
+#[test]
+fn output_dir_keeps_a_missing_report_missing() {
+    let mut config = Config::default_preset();
+    config.report_file = None;
+    config.set_output_dir("output");
+
+    assert_eq!(config.report_file, None);
+}
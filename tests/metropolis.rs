@@ -0,0 +1,77 @@
+extern crate mandelbuddha;
+
+use std::env;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use mandelbuddha::config::{Config, Sampler};
+use mandelbuddha::generate::generate;
+use mandelbuddha::image::ImageData;
+
+const BANDS: [(usize, usize); 3] = [(5, 10), (10, 20), (20, 50)];
+
+fn config(dir: &Path, samples: usize, sampler: Sampler) -> Config {
+    let mut config = Config::default_preset();
+    config.samples = samples;
+    config.sample_section = 10_000;
+    config.sampler = sampler;
+    config.seed = Some(5);
+    config.check_iterations = 50;
+    config.threads = 2;
+    config.thread_buffer = 10_000;
+    config.checkpoint_interval = 0;
+    config.report_file = None;
+    config.images[0].width = 100;
+    config.images[0].height = 100;
+    config.set_bands(&BANDS);
+    config.set_output_dir(dir);
+    config
+}
+
+/// Points recorded per sample in every band.
+fn band_totals(dir: &Path, samples: usize, sampler: Sampler) -> Vec<f64> {
+    fs::create_dir_all(dir).unwrap();
+    let config = config(dir, samples, sampler);
+    generate(config.clone(), false).unwrap();
+
+    config
+        .images
+        .iter()
+        .map(|image| {
+            let data = ImageData::read(&mut File::open(&image.file_name).unwrap()).unwrap();
+            data.sum() as f64 / samples as f64
+        }).collect()
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("mandelbuddha-{}-{}", name, process::id()))
+}
+
+#[test]
+fn metropolis_band_totals_match_uniform() {
+    let dir = temp_dir("metropolis");
+    let uniform = band_totals(&dir.join("uniform"), 2_000_000, Sampler::Uniform);
+    let metropolis = band_totals(
+        &dir.join("metropolis"),
+        200_000,
+        Sampler::Metropolis {
+            mutation_radius: 0.01,
+            large_step_probability: 0.1,
+            pilot_samples: 2_000_000,
+        },
+    );
+    fs::remove_dir_all(&dir).unwrap();
+
+    for ((&(min, max), uniform), metropolis) in BANDS.iter().zip(&uniform).zip(&metropolis) {
+        let error = (metropolis - uniform).abs() / uniform;
+        assert!(
+            error < 0.1,
+            "Band {}-{}: Metropolis records {} points per sample, uniform {}",
+            min,
+            max,
+            metropolis,
+            uniform
+        );
+    }
+}
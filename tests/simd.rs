@@ -0,0 +1,136 @@
+extern crate mandelbuddha;
+extern crate num;
+extern crate rand;
+
+use mandelbuddha::formulas::{Formula, Quadratic};
+use mandelbuddha::math::calculate_bailout_iteration;
+use mandelbuddha::simd::{quadratic_bailout_iterations, Kernel};
+use num::complex::Complex64;
+use rand::prng::XorShiftRng;
+use rand::{Rng, SeedableRng};
+
+fn kernels() -> Vec<Kernel> {
+    [Kernel::Lanes, Kernel::Avx2]
+        .iter()
+        .cloned()
+        .filter(|kernel| kernel.is_available())
+        .collect()
+}
+
+fn scalar(
+    cs: &[Complex64],
+    initial: Complex64,
+    bailout_min: Complex64,
+    bailout_max: Complex64,
+    max_iterations: usize,
+) -> Vec<Option<usize>> {
+    cs.iter()
+        .map(|&c| {
+            calculate_bailout_iteration(
+                &mut Quadratic::default().with_c(c),
+                initial,
+                bailout_min,
+                bailout_max,
+                max_iterations,
+            )
+        }).collect()
+}
+
+fn check(cs: &[Complex64], initial: Complex64, bailout: f64, max_iterations: usize) {
+    let bailout_min = Complex64::new(-bailout, -bailout);
+    let bailout_max = Complex64::new(bailout, bailout);
+    let expected = scalar(cs, initial, bailout_min, bailout_max, max_iterations);
+
+    for kernel in kernels() {
+        let mut results = vec![Some(usize::MAX); cs.len()];
+        quadratic_bailout_iterations(
+            kernel,
+            cs,
+            initial,
+            bailout_min,
+            bailout_max,
+            max_iterations,
+            &mut results,
+        );
+        for (i, (result, expected)) in results.iter().zip(&expected).enumerate() {
+            assert_eq!(result, expected, "{:?} differs for {}", kernel, cs[i]);
+        }
+    }
+}
+
+fn random_points(seed: u64, count: usize) -> Vec<Complex64> {
+    let mut rng = XorShiftRng::seed_from_u64(seed);
+    (0..count)
+        .map(|_| Complex64::new(rng.gen_range(-2.0, 2.0), rng.gen_range(-2.0, 2.0)))
+        .collect()
+}
+
+#[test]
+fn random_points_match_scalar() {
+    check(&random_points(1, 2000), Complex64::new(0.0, 0.0), 2.0, 1000);
+}
+
+#[test]
+fn bounded_and_periodic_points_match_scalar() {
+    let cs: Vec<_> = [
+        (0.0, 0.0),
+        (-1.0, 0.0),
+        (-0.1226, 0.7449),
+        (-1.7549, 0.0),
+        (0.25, 0.0),
+        (-0.75, 0.0),
+        (-2.0, 0.0),
+        (0.2501, 0.0),
+        (-0.7499, 0.001),
+    ].iter()
+        .map(|&(re, im)| Complex64::new(re, im))
+        .collect();
+    check(&cs, Complex64::new(0.0, 0.0), 2.0, 10_000);
+}
+
+#[test]
+fn iteration_limits_match_scalar() {
+    let cs = random_points(2, 500);
+    for &max_iterations in &[0, 1, 2, 3, 7, 64] {
+        check(&cs, Complex64::new(0.0, 0.0), 2.0, max_iterations);
+    }
+}
+
+#[test]
+fn initial_values_and_bailout_areas_match_scalar() {
+    let cs = random_points(3, 500);
+    check(&cs, Complex64::new(0.3, -0.2), 2.0, 500);
+    check(&cs, Complex64::new(3.0, 0.0), 2.0, 500);
+    check(&cs, Complex64::new(0.0, 0.0), 10.0, 500);
+    check(&cs, Complex64::new(0.0, 0.0), 0.5, 500);
+}
+
+#[test]
+fn batch_lengths_match_scalar() {
+    let cs = random_points(4, 20);
+    for length in 0..cs.len() {
+        check(&cs[..length], Complex64::new(0.0, 0.0), 2.0, 200);
+    }
+}
+
+#[test]
+fn results_beyond_the_points_are_untouched() {
+    let cs = random_points(5, 5);
+    for kernel in kernels() {
+        let mut results = vec![Some(usize::MAX); 8];
+        quadratic_bailout_iterations(
+            kernel,
+            &cs,
+            Complex64::new(0.0, 0.0),
+            Complex64::new(-2.0, -2.0),
+            Complex64::new(2.0, 2.0),
+            100,
+            &mut results,
+        );
+        assert!(
+            results[cs.len()..]
+                .iter()
+                .all(|&result| result == Some(usize::MAX))
+        );
+    }
+}